@@ -1,12 +1,25 @@
 
 
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use eframe::glow::Context;
-use egui::{Button, CentralPanel, SidePanel, TextureHandle, ThemePreference, TopBottomPanel};
+use egui::{
+    Button, CentralPanel, Key, KeyboardShortcut, Modifiers, SidePanel, TextureHandle,
+    ThemePreference, TopBottomPanel,
+};
 use strum::IntoEnumIterator;
 
-use crate::{image::MyImage, image_info::HistogramData, render::UiTab};
+use crate::{
+    file_stuff::default_directory,
+    filter::FilterStep,
+    frame_source::{FrameSource, SourceOptions},
+    gpu::GlPipeline,
+    history::{History, Trash},
+    image::{ExportFormat, JpegOptions, MyImage},
+    image_info::HistogramData,
+    photo_store::{self, MemoryStore, PhotoStore},
+    render::UiTab,
+};
 
 
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
@@ -19,8 +32,13 @@ pub struct MyApp {
     pub ui_tab: UiTab,
     pub save_options: SaveImageOptions,
     pub histogram: HistogramData,
+    pub source_options: SourceOptions,
+    /// Applied to every frame by `process_image`, in order.
+    pub filters: Vec<FilterStep>,
 
     pub photos: Vec<MyImage>,
+    /// Deleted photos, until they are restored or purged.
+    pub trash: Trash,
     
     #[serde(skip)] // This how you opt-out of serialization of a field
     pub gl:  Option<Arc<Context>>,
@@ -31,7 +49,42 @@ pub struct MyApp {
     #[serde(skip)] // This how you opt-out of serialization of a field
     pub photo: MyImage,
 
+    /// Where frames come from, see [`FrameSource`].
+    #[serde(skip)]
+    pub source: Box<dyn FrameSource>,
 
+    #[serde(skip)]
+    pub gl_pipeline: GlPipeline,
+
+    /// The photo on screen in the save tab, developed with its recipe. Keyed by the photo's
+    /// index and the recipe it was developed with.
+    #[serde(skip)]
+    pub developed: Option<(usize, Vec<FilterStep>, MyImage)>,
+
+    /// Size of `developed` as a jpeg, and the options it was encoded with.
+    #[serde(skip)]
+    pub jpeg_estimate: Option<(usize, Vec<FilterStep>, JpegOptions, usize)>,
+
+    /// Small copies of `photos` for the gallery, keyed by photo id, with the recipe they
+    /// were made with.
+    #[serde(skip)]
+    pub thumbnails: HashMap<String, (Vec<FilterStep>, TextureHandle)>,
+
+    /// Undo and redo for `photos`.
+    #[serde(skip)]
+    pub history: History,
+
+    /// Where the pixels of `photos` are kept.
+    #[serde(skip)]
+    pub store: Box<dyn PhotoStore>,
+
+    /// How the last export went.
+    #[serde(skip)]
+    pub export_status: Option<String>,
+
+    /// Why the current source failed to start, if it did.
+    #[serde(skip)]
+    pub source_error: Option<String>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -40,6 +93,22 @@ pub struct SaveImageOptions {
     pub raw_png: bool,
     pub jpg: bool,
     pub png: bool,
+    pub webp: bool,
+    pub tiff: bool,
+    pub bmp: bool,
+    pub qoi: bool,
+    pub jpeg: JpegOptions,
+    /// leave the exif block (time, camera, filters) out of exported jpegs
+    pub strip_metadata: bool,
+    /// how exported files are named, see [`crate::file_name::TOKENS`]
+    pub name_template: String,
+    /// put in front of every file name, only kept until the app is closed
+    #[serde(skip)]
+    pub session_prefix: String,
+    /// put everything in one zip, rather than separate files (the web always zips)
+    pub zip: bool,
+    /// the folder exports are written to on native
+    pub directory: String,
     pub image_index: i32,
 }
 
@@ -49,21 +118,71 @@ impl Default for SaveImageOptions {
             raw_png: false,
             jpg: true,
             png: false,
+            webp: false,
+            tiff: false,
+            bmp: false,
+            qoi: false,
+            jpeg: JpegOptions::default(),
+            strip_metadata: false,
+            name_template: "{date} {time}".to_owned(),
+            session_prefix: String::new(),
+            zip: true,
+            directory: default_directory(),
             image_index: 0,
         }
     }
 }
+
+impl SaveImageOptions {
+    /// Whether each photo gets written out as `format`.
+    pub fn format_mut(&mut self, format: ExportFormat) -> &mut bool {
+        match format {
+            ExportFormat::Jpeg => &mut self.jpg,
+            ExportFormat::Png => &mut self.png,
+            ExportFormat::WebP => &mut self.webp,
+            ExportFormat::Tiff => &mut self.tiff,
+            ExportFormat::Bmp => &mut self.bmp,
+            ExportFormat::Qoi => &mut self.qoi,
+        }
+    }
+
+    pub fn formats(&self) -> Vec<ExportFormat> {
+        [
+            (ExportFormat::Jpeg, self.jpg),
+            (ExportFormat::Png, self.png),
+            (ExportFormat::WebP, self.webp),
+            (ExportFormat::Tiff, self.tiff),
+            (ExportFormat::Bmp, self.bmp),
+            (ExportFormat::Qoi, self.qoi),
+        ]
+        .into_iter()
+        .filter_map(|(format, enabled)| enabled.then_some(format))
+        .collect()
+    }
+}
 impl Default for MyApp {
     fn default() -> Self {
         
         Self {
             texture: None,
             photos: vec![],
+            trash: Trash::default(),
+            history: History::default(),
             ui_tab: UiTab::default(),
             save_options: SaveImageOptions::default(),
             gl: None,
             photo: MyImage::default(),
             histogram: HistogramData::default(),
+            source_options: SourceOptions::default(),
+            source: SourceOptions::default().build(),
+            source_error: None,
+            filters: vec![],
+            developed: None,
+            jpeg_estimate: None,
+            thumbnails: HashMap::new(),
+            export_status: None,
+            store: Box::<MemoryStore>::default(),
+            gl_pipeline: GlPipeline::default(),
         }
     }
 }
@@ -85,37 +204,71 @@ impl MyApp {
         let mut fonts = egui::FontDefinitions::default();
         egui_phosphor::add_to_fonts(&mut fonts, egui_phosphor::Variant::Regular);
         cc.egui_ctx.set_fonts(fonts);
-        cc.egui_ctx.all_styles_mut(|style| {
-            
-        });
         cc.egui_ctx.tessellation_options_mut(|tess_options| {
             tess_options.feathering = false;
         });
 
 
-        if let Some(storage) = cc.storage {
-            return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
-        }
-
-        let mut s = MyApp::default();
+        let mut s: MyApp = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, eframe::APP_KEY))
+            .unwrap_or_default();
         s.gl = cc.gl.clone();
-        return s;
+        s.store = photo_store::open();
+        // photos saved before there was a photo store still have their pixels in the app state
+        for i in 0..s.photos.len() {
+            if s.photos[i].id.is_empty() {
+                if let Err(e) = s.store_photo(i) {
+                    log::error!(
+                        "failed to move {} into the photo store: {e:?}",
+                        s.photos[i].name
+                    );
+                }
+            }
+        }
+        s.purge_trash();
+        s.restart_source();
+        s
     }
 
     pub fn update_texture(&mut self, ctx: &egui::Context) -> anyhow::Result<()> {
-        let mut perm_img = MyImage::default();
-        let img;
-        self.save_options.image_index = self.save_options.image_index.clamp(0, (self.photos.len() as i32 -1).max(0));
-        if self.ui_tab == UiTab::SavePhoto && self.save_options.image_index < self.photos.len() as i32 && self.photos.len()>0 {
-            
-            img = &self.photos[self.save_options.image_index as usize];
-            self.process_image(&mut perm_img);
-        }else {
-            perm_img = self.capture_frame(false)?;
-            self.process_image(&mut perm_img);
-            self.photo = perm_img;
-            img = &self.photo;
-        }
+        self.save_options.image_index = self
+            .save_options
+            .image_index
+            .clamp(0, (self.photos.len() as i32 - 1).max(0));
+        let img = if self.ui_tab == UiTab::SavePhoto
+            && self.save_options.image_index < self.photos.len() as i32
+            && !self.photos.is_empty()
+        {
+            let index = self.save_options.image_index as usize;
+            let recipe = &self.photos[index].recipe;
+            let developed = match self.developed.take() {
+                Some((i, r, img)) if i == index && &r == recipe => img,
+                _ => self.develop(index)?,
+            };
+            &self
+                .developed
+                .insert((index, developed.recipe.clone(), developed))
+                .2
+        } else {
+            // the region is metered on whatever the histogram is of, so show that
+            let histogram_photo =
+                self.ui_tab == UiTab::Histogram && self.histogram.photo().is_some();
+            match self.capture_frame(false) {
+                Ok(mut perm_img) => {
+                    let filters = self.filters.clone();
+                    self.process_image(&mut perm_img, &filters);
+                    self.photo = perm_img;
+                }
+                // still wanted for comparing against, but it isn't what's on screen
+                Err(_) if histogram_photo => {}
+                Err(e) => return Err(e),
+            }
+            match self.histogram.photo() {
+                Some(photo) if histogram_photo => photo,
+                _ => &self.photo,
+            }
+        };
         match self.texture {
             Some(ref mut a) if a.size() == [img.width as usize, img.height as usize]  => {
                 (*a).set_partial([0,0], egui::ColorImage::from_rgba_premultiplied([img.width as usize,img.height as usize], &img.bytes), egui::TextureOptions {
@@ -140,13 +293,32 @@ impl eframe::App for MyApp {
         eframe::set_value(storage, eframe::APP_KEY, self);
     }
 
+    fn on_exit(&mut self, gl: Option<&Context>) {
+        self.source.stop();
+        if let Some(gl) = gl {
+            self.gl_pipeline.destroy(gl);
+        }
+    }
+
 
 
 
     /// Called each time the UI needs repainting, which may be many times per second.
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // text fields have their own undo
+        if ctx.memory(|m| m.focused().is_none()) {
+            let redo = KeyboardShortcut::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z);
+            let undo = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+            // redo first, undo would match ctrl+shift+z too
+            if ctx.input_mut(|i| i.consume_shortcut(&redo)) {
+                self.redo();
+            } else if ctx.input_mut(|i| i.consume_shortcut(&undo)) {
+                self.undo();
+            }
+        }
 
-        self.update_texture(ctx);
+        // a missing frame just leaves the last texture on screen
+        self.update_texture(ctx).ok();
         let landscape = ctx.screen_rect().aspect_ratio() > 1.0;
 
         if landscape {
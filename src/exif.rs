@@ -0,0 +1,335 @@
+use chrono::{DateTime, Local};
+
+use crate::{filter::describe, image::MyImage};
+
+/// The software tag written into every photo.
+const SOFTWARE: &str = concat!("webcamera ", env!("CARGO_PKG_VERSION"));
+
+/// The longest user comment we write, the whole exif block has to fit in one 64k segment.
+const MAX_COMMENT: usize = 4096;
+
+/// Metadata for an exported photo, written as an exif block.
+pub struct Exif {
+    pub taken: DateTime<Local>,
+    pub width: u32,
+    pub height: u32,
+    /// the camera (or other source) the photo came from
+    pub device: String,
+    pub comment: String,
+}
+
+impl Exif {
+    pub fn for_photo(img: &MyImage) -> Exif {
+        let filters = describe(&img.recipe);
+        Exif {
+            taken: img.taken(),
+            width: img.width,
+            height: img.height,
+            device: img.device.clone(),
+            comment: if filters.is_empty() {
+                String::new()
+            } else {
+                format!("filters: {filters}")
+            },
+        }
+    }
+
+    /// The payload of a jpeg APP1 segment: the exif header followed by a little endian tiff
+    /// structure holding IFD0 and the Exif IFD.
+    pub fn to_app1(&self) -> Vec<u8> {
+        let date = self.taken.format("%Y:%m:%d %H:%M:%S").to_string();
+
+        let mut ifd0 = vec![
+            (0x0100, Value::Long(self.width)),
+            (0x0101, Value::Long(self.height)),
+        ];
+        if !self.device.is_empty() {
+            ifd0.push((0x0110, Value::Ascii(self.device.clone())));
+        }
+        ifd0.extend([
+            // the pixels are always stored the right way up
+            (0x0112, Value::Short(1)),
+            (0x0131, Value::Ascii(SOFTWARE.to_owned())),
+            (0x0132, Value::Ascii(date.clone())),
+            // patched with the real offset once it is known
+            (0x8769, Value::Long(0)),
+        ]);
+
+        let mut exif_ifd = vec![
+            (0x9000, Value::Undefined(b"0232".to_vec())),
+            (0x9003, Value::Ascii(date)),
+            (0x9011, Value::Ascii(self.taken.format("%:z").to_string())),
+        ];
+        if !self.comment.is_empty() {
+            let mut comment = b"ASCII\0\0\0".to_vec();
+            comment.extend(ascii(&self.comment).bytes().take(MAX_COMMENT));
+            exif_ifd.push((0x9286, Value::Undefined(comment)));
+        }
+        exif_ifd.extend([
+            (0x9291, Value::Ascii(self.taken.format("%3f").to_string())),
+            (0xA002, Value::Long(self.width)),
+            (0xA003, Value::Long(self.height)),
+        ]);
+
+        // "II" for little endian, the magic number 42, then IFD0 straight after
+        let mut tiff = vec![b'I', b'I', 42, 0, 8, 0, 0, 0];
+        let pointer = write_ifd(&mut tiff, &ifd0) + 8;
+        let exif_offset = tiff.len() as u32;
+        tiff[pointer..pointer + 4].copy_from_slice(&exif_offset.to_le_bytes());
+        write_ifd(&mut tiff, &exif_ifd);
+
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend(tiff);
+        app1
+    }
+}
+
+enum Value {
+    Short(u16),
+    Long(u32),
+    Ascii(String),
+    Undefined(Vec<u8>),
+}
+
+impl Value {
+    /// The tiff field type, the number of values and the raw bytes.
+    fn encode(&self) -> (u16, u32, Vec<u8>) {
+        match self {
+            Value::Short(v) => (3, 1, v.to_le_bytes().to_vec()),
+            Value::Long(v) => (4, 1, v.to_le_bytes().to_vec()),
+            Value::Ascii(s) => {
+                let mut bytes = ascii(s).into_bytes();
+                bytes.push(0);
+                (2, bytes.len() as u32, bytes)
+            }
+            Value::Undefined(bytes) => (7, bytes.len() as u32, bytes.clone()),
+        }
+    }
+}
+
+/// Exif strings are ascii only.
+fn ascii(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii() && c != '\0' { c } else { '?' })
+        .collect()
+}
+
+/// Appends an IFD (entries sorted by tag) and the values that don't fit in its entries.
+/// Returns the position of the last entry, where a sub-IFD pointer would go.
+fn write_ifd(out: &mut Vec<u8>, entries: &[(u16, Value)]) -> usize {
+    let start = out.len();
+    let mut data_offset = start + 2 + entries.len() * 12 + 4;
+    let mut data = vec![];
+    let mut last = start;
+
+    out.extend((entries.len() as u16).to_le_bytes());
+    for (tag, value) in entries {
+        let (kind, count, mut bytes) = value.encode();
+        last = out.len();
+        out.extend(tag.to_le_bytes());
+        out.extend(kind.to_le_bytes());
+        out.extend(count.to_le_bytes());
+        if bytes.len() <= 4 {
+            bytes.resize(4, 0);
+            out.extend(bytes);
+        } else {
+            out.extend((data_offset as u32).to_le_bytes());
+            // values have to start on a word boundary
+            if bytes.len() % 2 == 1 {
+                bytes.push(0);
+            }
+            data_offset += bytes.len();
+            data.extend(bytes);
+        }
+    }
+    // no next IFD
+    out.extend(0u32.to_le_bytes());
+    out.extend(data);
+    last
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::image::JpegOptions;
+
+    struct Entry {
+        tag: u16,
+        kind: u16,
+        count: u32,
+        /// where the value is, either inside the entry or out in the data after the IFD
+        offset: usize,
+        inline: bool,
+    }
+
+    fn u16_at(b: &[u8], i: usize) -> u16 {
+        u16::from_le_bytes([b[i], b[i + 1]])
+    }
+
+    fn u32_at(b: &[u8], i: usize) -> u32 {
+        u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+    }
+
+    /// The exif payload of the jpeg's APP1 segment, without the "Exif\0\0" header.
+    fn tiff_in(jpeg: &[u8]) -> Vec<u8> {
+        let mut i = 2;
+        while jpeg[i] == 0xFF && jpeg[i + 1] != 0xDA {
+            let len = u16::from_be_bytes([jpeg[i + 2], jpeg[i + 3]]) as usize;
+            let payload = &jpeg[i + 4..i + 2 + len];
+            if jpeg[i + 1] == 0xE1 && payload.starts_with(b"Exif\0\0") {
+                return payload[6..].to_vec();
+            }
+            i += 2 + len;
+        }
+        panic!("no exif segment");
+    }
+
+    /// Reads the IFD at `start`, checking it has no next IFD.
+    fn read_ifd(tiff: &[u8], start: usize) -> Vec<Entry> {
+        let count = u16_at(tiff, start) as usize;
+        let entries: Vec<Entry> = (0..count)
+            .map(|n| {
+                let at = start + 2 + n * 12;
+                let kind = u16_at(tiff, at + 2);
+                let count = u32_at(tiff, at + 4);
+                let size = match kind {
+                    3 => 2,
+                    4 => 4,
+                    _ => 1,
+                } * count as usize;
+                Entry {
+                    tag: u16_at(tiff, at),
+                    kind,
+                    count,
+                    offset: if size <= 4 {
+                        at + 8
+                    } else {
+                        u32_at(tiff, at + 8) as usize
+                    },
+                    inline: size <= 4,
+                }
+            })
+            .collect();
+        assert_eq!(u32_at(tiff, start + 2 + count * 12), 0);
+        entries
+    }
+
+    fn value<'a>(tiff: &'a [u8], entries: &[Entry], tag: u16) -> &'a [u8] {
+        let entry = entries.iter().find(|e| e.tag == tag).expect("missing tag");
+        &tiff[entry.offset..entry.offset + entry.count as usize]
+    }
+
+    fn long(tiff: &[u8], entries: &[Entry], tag: u16) -> u32 {
+        let entry = entries.iter().find(|e| e.tag == tag).expect("missing tag");
+        assert_eq!((entry.kind, entry.count), (4, 1));
+        u32_at(tiff, entry.offset)
+    }
+
+    fn exif(comment: &str) -> Exif {
+        Exif {
+            taken: Local
+                .with_ymd_and_hms(2024, 5, 6, 7, 8, 9)
+                .earliest()
+                .unwrap(),
+            width: 8,
+            height: 6,
+            // odd length with its terminator, so it needs padding
+            device: "Webcam".to_owned(),
+            comment: comment.to_owned(),
+        }
+    }
+
+    fn round_trip(exif: &Exif) -> Vec<u8> {
+        let img = ::image::DynamicImage::new_rgb8(exif.width, exif.height);
+        let jpeg = JpegOptions::default()
+            .encode(&img, Some(&exif.to_app1()))
+            .unwrap();
+        tiff_in(&jpeg)
+    }
+
+    #[test]
+    fn exif_survives_a_jpeg() {
+        let tiff = round_trip(&exif("filters: sharpen"));
+        assert_eq!(&tiff[..8], &[b'I', b'I', 42, 0, 8, 0, 0, 0]);
+
+        let ifd0 = read_ifd(&tiff, 8);
+        let tags: Vec<u16> = ifd0.iter().map(|e| e.tag).collect();
+        assert_eq!(
+            tags,
+            [0x0100, 0x0101, 0x0110, 0x0112, 0x0131, 0x0132, 0x8769]
+        );
+        assert_eq!(long(&tiff, &ifd0, 0x0100), 8);
+        assert_eq!(long(&tiff, &ifd0, 0x0101), 6);
+        assert_eq!(value(&tiff, &ifd0, 0x0110), b"Webcam\0");
+        assert_eq!(
+            value(&tiff, &ifd0, 0x0131),
+            format!("{SOFTWARE}\0").as_bytes()
+        );
+        assert_eq!(value(&tiff, &ifd0, 0x0132), b"2024:05:06 07:08:09\0");
+
+        let exif_ifd = read_ifd(&tiff, long(&tiff, &ifd0, 0x8769) as usize);
+        let tags: Vec<u16> = exif_ifd.iter().map(|e| e.tag).collect();
+        assert_eq!(
+            tags,
+            [0x9000, 0x9003, 0x9011, 0x9286, 0x9291, 0xA002, 0xA003]
+        );
+        assert_eq!(value(&tiff, &exif_ifd, 0x9000), b"0232");
+        assert_eq!(value(&tiff, &exif_ifd, 0x9003), b"2024:05:06 07:08:09\0");
+        assert_eq!(
+            value(&tiff, &exif_ifd, 0x9286),
+            b"ASCII\0\0\0filters: sharpen"
+        );
+        assert_eq!(value(&tiff, &exif_ifd, 0x9291), b"000\0");
+        assert_eq!(long(&tiff, &exif_ifd, 0xA002), 8);
+        assert_eq!(long(&tiff, &exif_ifd, 0xA003), 6);
+    }
+
+    #[test]
+    fn values_are_laid_out_after_their_ifd() {
+        let tiff = round_trip(&exif("an odd comment"));
+        let ifd0 = read_ifd(&tiff, 8);
+        let exif_offset = long(&tiff, &ifd0, 0x8769) as usize;
+        let exif_ifd = read_ifd(&tiff, exif_offset);
+
+        for (start, entries, end) in [
+            (8, &ifd0, exif_offset),
+            (exif_offset, &exif_ifd, tiff.len()),
+        ] {
+            // each value starts where the last one (padded to an even length) finished,
+            // and the last one ends where the next IFD or the segment starts
+            let mut next = start + 2 + entries.len() * 12 + 4;
+            for entry in entries.iter().filter(|e| !e.inline) {
+                assert_eq!(entry.offset, next, "tag {:#x}", entry.tag);
+                next += entry.count as usize + entry.count as usize % 2;
+            }
+            assert_eq!(next, end);
+        }
+        // "Webcam\0" is padded with a zero
+        let model = ifd0.iter().find(|e| e.tag == 0x0110).unwrap();
+        assert_eq!(tiff[model.offset + 7], 0);
+    }
+
+    #[test]
+    fn long_comments_are_cut_short() {
+        let tiff = round_trip(&exif(&"x".repeat(5000)));
+        let ifd0 = read_ifd(&tiff, 8);
+        let exif_ifd = read_ifd(&tiff, long(&tiff, &ifd0, 0x8769) as usize);
+        let comment = value(&tiff, &exif_ifd, 0x9286);
+        assert_eq!(comment.len(), 8 + MAX_COMMENT);
+        assert!(comment[8..].iter().all(|&c| c == b'x'));
+    }
+
+    #[test]
+    fn empty_fields_are_left_out() {
+        let tiff = round_trip(&Exif {
+            device: String::new(),
+            ..exif("")
+        });
+        let ifd0 = read_ifd(&tiff, 8);
+        assert!(ifd0.iter().all(|e| e.tag != 0x0110));
+        let exif_ifd = read_ifd(&tiff, long(&tiff, &ifd0, 0x8769) as usize);
+        assert!(exif_ifd.iter().all(|e| e.tag != 0x9286));
+    }
+}
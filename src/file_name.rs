@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+
+use crate::{filter::FilterStep, image::MyImage};
+
+/// The tokens a file name template can use, with what they turn into.
+pub const TOKENS: [(&str, &str); 8] = [
+    ("{date}", "day the photo was taken, 2024-01-31"),
+    ("{time}", "time it was taken, 13-45-07"),
+    ("{ms}", "milliseconds, 042"),
+    ("{index}", "position in the photo list, 001"),
+    ("{width}", "width in pixels"),
+    ("{height}", "height in pixels"),
+    ("{filter}", "the filters used, or \"original\""),
+    ("{name}", "the photo's name"),
+];
+
+fn filter_names(recipe: &[FilterStep]) -> String {
+    let names = recipe
+        .iter()
+        .filter(|step| step.enabled)
+        .map(|step| step.kind.name().replace(' ', "-"))
+        .collect::<Vec<String>>();
+    if names.is_empty() {
+        "original".to_owned()
+    } else {
+        names.join("+")
+    }
+}
+
+/// Fills in `template` for one photo. `index` is the photo's place in the list, from 0.
+/// Unknown tokens are left as they are.
+pub fn fill_template(template: &str, photo: &MyImage, index: usize) -> String {
+    let taken = photo.taken();
+    let name = template
+        .replace("{date}", &taken.format("%Y-%m-%d").to_string())
+        .replace("{time}", &taken.format("%H-%M-%S").to_string())
+        .replace("{ms}", &taken.format("%3f").to_string())
+        .replace("{index}", &format!("{:03}", index + 1))
+        .replace("{width}", &photo.width.to_string())
+        .replace("{height}", &photo.height.to_string())
+        .replace("{filter}", &filter_names(&photo.recipe))
+        .replace("{name}", &photo.name);
+    sanitize(&name)
+}
+
+/// Swaps out characters that aren't allowed in file names on some systems.
+fn sanitize(name: &str) -> String {
+    let name = name
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    if name.is_empty() {
+        "photo".to_owned()
+    } else {
+        name
+    }
+}
+
+/// File names (without extensions) for the photos at `indices`, in the same order. Names
+/// that come out the same get " (1)", " (2)", ... added so nothing gets overwritten.
+pub fn export_names(
+    template: &str,
+    prefix: &str,
+    photos: &[MyImage],
+    indices: &[usize],
+) -> Vec<String> {
+    let mut used = HashSet::new();
+    indices
+        .iter()
+        .map(|&i| {
+            let base = sanitize(&format!(
+                "{prefix}{}",
+                fill_template(template, &photos[i], i)
+            ));
+            let mut name = base.clone();
+            let mut n = 1;
+            // names are compared ignoring case, some file systems do
+            while !used.insert(name.to_lowercase()) {
+                name = format!("{base} ({n})");
+                n += 1;
+            }
+            name
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterKind;
+
+    fn photo(name: &str) -> MyImage {
+        MyImage {
+            name: name.to_owned(),
+            width: 640,
+            height: 480,
+            // so the time comes from the name
+            taken_ms: 0,
+            ..Default::default()
+        }
+    }
+
+    fn names(template: &str, photos: &[&str]) -> Vec<String> {
+        let photos: Vec<MyImage> = photos.iter().map(|name| photo(name)).collect();
+        let indices: Vec<usize> = (0..photos.len()).collect();
+        export_names(template, "", &photos, &indices)
+    }
+
+    #[test]
+    fn every_token_is_filled_in() {
+        let mut img = photo("2024-01-31 13-45-07");
+        img.recipe = vec![
+            FilterKind::HueRotate.into(),
+            FilterKind::Invert.into(),
+            FilterStep {
+                enabled: false,
+                ..FilterKind::Sepia.into()
+            },
+        ];
+        for (token, _) in TOKENS {
+            let expected = match token {
+                "{date}" => "2024-01-31",
+                "{time}" => "13-45-07",
+                "{ms}" => "000",
+                "{index}" => "005",
+                "{width}" => "640",
+                "{height}" => "480",
+                "{filter}" => "hue-rotate+invert",
+                "{name}" => "2024-01-31 13-45-07",
+                _ => panic!("no test for {token}"),
+            };
+            assert_eq!(fill_template(token, &img, 4), expected, "{token}");
+        }
+        assert_eq!(fill_template("{filter}", &photo("x"), 0), "original");
+        assert_eq!(fill_template("{nope}_{width}", &img, 0), "{nope}_640");
+    }
+
+    #[test]
+    fn unsafe_characters_are_replaced() {
+        assert_eq!(
+            sanitize("a/b\\c:d*e?f\"g<h>i|j\tk"),
+            "a_b_c_d_e_f_g_h_i_j_k"
+        );
+        assert_eq!(sanitize("  spaced out  "), "spaced out");
+        // tokens can bring in separators too
+        assert_eq!(fill_template("{name}", &photo("../up"), 0), ".._up");
+    }
+
+    #[test]
+    fn empty_templates_still_give_a_name() {
+        assert_eq!(fill_template("", &photo("x"), 0), "photo");
+        assert_eq!(fill_template(" \t ", &photo("x"), 0), "photo");
+        assert_eq!(names("  ", &["x", "y"]), ["photo", "photo (1)"]);
+    }
+
+    #[test]
+    fn clashing_names_are_numbered() {
+        assert_eq!(
+            names("{name}", &["a", "b", "a", "a"]),
+            ["a", "b", "a (1)", "a (2)"]
+        );
+        // some file systems ignore case, so these would overwrite each other
+        assert_eq!(names("{name}", &["a", "A"]), ["a", "A (1)"]);
+        assert_eq!(
+            names("{name}", &["a", "a", "a (1)"]),
+            ["a", "a (1)", "a (1) (1)"]
+        );
+    }
+}
@@ -1,34 +1,205 @@
-use wasm_bindgen::JsCast;
-use web_sys::window;
-
-use anyhow::anyhow;
-
-pub fn download_zip_file(bytes: Vec<u8>, filename:String) -> anyhow::Result<()> {
-    download_file(bytes, filename, "application/zip".to_owned())
-}
-pub fn download_file(bytes: Vec<u8>, filename:String, datatype:String) -> anyhow::Result<()> {
-    let link = window()
-    .ok_or(anyhow!("no window found"))
-    ? 
-    .document()
-    .ok_or(anyhow!("no document found"))
-    
-    ?.get_element_by_id("download_files")
-    .ok_or(anyhow!("could not find element called download_files"))
-    ?;
-    
-    let link = link.dyn_into::<web_sys::HtmlElement>()
-    .map_err(|_| ())
-    .unwrap();
-    let base = base64_url::encode(&bytes);
-
-    let a = link.set_attribute("href", &format!("data:{datatype};base64,{base}"));
-    let b = link.set_attribute("download", &format!(""));
-    if a.is_ok() && b.is_ok() {
-        link.click();
-    }else {
-        return Err(anyhow!("{:?} {:?}", a,b));
-    }
-    ;
-    Ok(())
-}
\ No newline at end of file
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use async_zip::{base::write::ZipFileWriter, Compression, ZipEntryBuilder};
+
+/// One finished file, ready to be handed to the user.
+pub struct ExportFile {
+    pub name: String,
+    pub bytes: Vec<u8>,
+    /// whether it's worth deflating when it goes into a zip
+    pub deflate: bool,
+}
+
+/// Somewhere exported files end up.
+pub trait ExportTarget {
+    fn write(&self, name: &str, bytes: Vec<u8>) -> anyhow::Result<()>;
+    /// Where the files went, for telling the user.
+    fn describe(&self) -> String;
+}
+
+pub async fn zip_files(files: Vec<ExportFile>) -> anyhow::Result<Vec<u8>> {
+    let mut zip: Vec<u8> = vec![];
+    let mut writer = ZipFileWriter::new(&mut zip);
+    for file in files {
+        let compression = if file.deflate {
+            Compression::Deflate
+        } else {
+            Compression::Stored
+        };
+        writer
+            .write_entry_whole(
+                ZipEntryBuilder::new(file.name.into(), compression),
+                &file.bytes,
+            )
+            .await?;
+    }
+    Ok(writer.close().await?.to_vec())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::Directory;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::path::PathBuf;
+
+    use super::ExportTarget;
+
+    /// Writes files into a folder on disk, without overwriting anything already there.
+    pub struct Directory(pub PathBuf);
+
+    impl ExportTarget for Directory {
+        fn write(&self, name: &str, bytes: Vec<u8>) -> anyhow::Result<()> {
+            std::fs::create_dir_all(&self.0)?;
+            let name = PathBuf::from(name);
+            let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+            let extension = name
+                .extension()
+                .map(|e| format!(".{}", e.to_string_lossy()))
+                .unwrap_or_default();
+            let mut path = self.0.join(&name);
+            let mut n = 2;
+            while path.exists() {
+                path = self.0.join(format!("{stem} ({n}){extension}"));
+                n += 1;
+            }
+            std::fs::write(&path, bytes)?;
+            Ok(())
+        }
+
+        fn describe(&self) -> String {
+            self.0.display().to_string()
+        }
+    }
+}
+
+/// Where exports go until someone picks somewhere else. Empty on the web, which always
+/// downloads instead.
+pub fn default_directory() -> String {
+    #[cfg(target_arch = "wasm32")]
+    return String::new();
+    #[cfg(not(target_arch = "wasm32"))]
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| Path::new(&home).join("Pictures").join("webcamera"))
+        .unwrap_or_else(|| "webcamera".into())
+        .display()
+        .to_string()
+}
+
+#[cfg(any(target_arch = "wasm32", test))]
+fn mime_type(name: &str) -> &'static str {
+    let extension = std::path::Path::new(name)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "zip" => "application/zip",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "webp" => "image/webp",
+        "tiff" => "image/tiff",
+        "bmp" => "image/bmp",
+        "qoi" => "image/qoi",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use web::DownloadLink;
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use anyhow::anyhow;
+    use js_sys::{Array, Uint8Array};
+    use wasm_bindgen::{closure::Closure, JsCast};
+    use web_sys::{window, Blob, BlobPropertyBag, Url};
+
+    use super::{mime_type, ExportTarget};
+
+    /// Downloads through the hidden `download_files` link in `index.html`.
+    pub struct DownloadLink;
+
+    impl ExportTarget for DownloadLink {
+        fn write(&self, name: &str, bytes: Vec<u8>) -> anyhow::Result<()> {
+            download_file(bytes, name, mime_type(name))
+        }
+
+        fn describe(&self) -> String {
+            "downloads".to_owned()
+        }
+    }
+
+    /// Downloads `bytes` as a file. The data goes into a blob the link points at, rather
+    /// than a data url, so large zips don't have to be copied into a giant string.
+    pub fn download_file(bytes: Vec<u8>, filename: &str, datatype: &str) -> anyhow::Result<()> {
+        let window = window().ok_or(anyhow!("no window found"))?;
+        let link = window
+            .document()
+            .ok_or(anyhow!("no document found"))?
+            .get_element_by_id("download_files")
+            .ok_or(anyhow!("could not find element called download_files"))?
+            .dyn_into::<web_sys::HtmlElement>()
+            .map_err(|_| anyhow!("download_files is not an html element"))?;
+
+        let options = BlobPropertyBag::new();
+        options.set_type(datatype);
+        let parts = Array::of1(&Uint8Array::from(bytes.as_slice()));
+        let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+            .map_err(|e| anyhow!("failed to make a blob: {e:?}"))?;
+        let url = Url::create_object_url_with_blob(&blob)
+            .map_err(|e| anyhow!("failed to make a url: {e:?}"))?;
+
+        let result = link
+            .set_attribute("href", &url)
+            .and_then(|_| link.set_attribute("download", filename));
+        if result.is_ok() {
+            link.click();
+        }
+
+        // the download may not have started reading the blob yet, so give it a while
+        let revoke = Closure::once_into_js(move || {
+            Url::revoke_object_url(&url).ok();
+        });
+        window
+            .set_timeout_with_callback_and_timeout_and_arguments_0(revoke.unchecked_ref(), 60_000)
+            .map_err(|e| anyhow!("{e:?}"))?;
+        link.remove_attribute("href").ok();
+
+        result.map_err(|e| anyhow!("failed to set up the download: {e:?}"))
+    }
+}
+
+/// Where a finished export should go, `directory` is ignored on the web.
+pub fn export_target(directory: &str) -> anyhow::Result<Box<dyn ExportTarget>> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = directory;
+        Ok(Box::new(DownloadLink))
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if directory.trim().is_empty() {
+            return Err(anyhow::anyhow!("pick a folder to save to"));
+        }
+        Ok(Box::new(Directory(directory.trim().into())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use strum::IntoEnumIterator;
+
+    use super::*;
+    use crate::image::ExportFormat;
+
+    #[test]
+    fn every_export_format_has_a_mime_type() {
+        for format in ExportFormat::iter() {
+            let name = format!("photo.{}", format.extension());
+            assert_ne!(mime_type(&name), "application/octet-stream", "{name}");
+        }
+        assert_eq!(mime_type("photos.ZIP"), "application/zip");
+    }
+}
@@ -0,0 +1,445 @@
+use std::f32::consts::PI;
+
+use egui::{RichText, Ui};
+use egui_phosphor::regular::{ARROW_DOWN, ARROW_UP, X};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use crate::{gpu::Pass, image::MyImage, MyApp};
+
+/// Rec. 709 luma weights, used by everything that needs "how bright is this pixel".
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+pub fn luma(rgb: [f32; 3]) -> f32 {
+    rgb[0] * LUMA[0] + rgb[1] * LUMA[1] + rgb[2] * LUMA[2]
+}
+
+fn mix(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+fn mul(m: [[f32; 3]; 3], rgb: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2])
+}
+
+/// Hue rotation matrix, the same one css `hue-rotate()` uses.
+fn hue_matrix(degrees: f32) -> [[f32; 3]; 3] {
+    let (sin, cos) = (degrees * PI / 180.0).sin_cos();
+    [
+        [
+            0.213 + cos * 0.787 - sin * 0.213,
+            0.715 - cos * 0.715 - sin * 0.715,
+            0.072 - cos * 0.072 + sin * 0.928,
+        ],
+        [
+            0.213 - cos * 0.213 + sin * 0.143,
+            0.715 + cos * 0.285 + sin * 0.140,
+            0.072 - cos * 0.072 - sin * 0.283,
+        ],
+        [
+            0.213 - cos * 0.213 - sin * 0.787,
+            0.715 - cos * 0.715 + sin * 0.715,
+            0.072 + cos * 0.928 + sin * 0.072,
+        ],
+    ]
+}
+
+const SEPIA: [[f32; 3]; 3] = [
+    [0.393, 0.769, 0.189],
+    [0.349, 0.686, 0.168],
+    [0.272, 0.534, 0.131],
+];
+
+/// A colour adjustment done on the cpu, one pixel at a time.
+///
+/// Channels are in `0.0..=1.0` and alpha is left alone. Implementations don't need to
+/// clamp, [`Filter::apply`] does that after every step like the gpu would.
+pub trait Filter {
+    fn pixel(&self, rgb: [f32; 3]) -> [f32; 3];
+
+    fn apply(&self, img: &mut MyImage) {
+        for px in img.bytes.chunks_exact_mut(4) {
+            let rgb = self.pixel([0, 1, 2].map(|i| px[i] as f32 / 255.0));
+            for i in 0..3 {
+                px[i] = (rgb[i].clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, EnumIter, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FilterKind {
+    Brightness,
+    Contrast,
+    Saturation,
+    Gamma,
+    HueRotate,
+    Invert,
+    Grayscale,
+    Sepia,
+    Threshold,
+}
+
+impl FilterKind {
+    pub fn name(&self) -> &str {
+        match self {
+            FilterKind::Brightness => "brightness",
+            FilterKind::Contrast => "contrast",
+            FilterKind::Saturation => "saturation",
+            FilterKind::Gamma => "gamma",
+            FilterKind::HueRotate => "hue rotate",
+            FilterKind::Invert => "invert",
+            FilterKind::Grayscale => "grayscale",
+            FilterKind::Sepia => "sepia",
+            FilterKind::Threshold => "threshold",
+        }
+    }
+
+    /// The sensible values for `amount`.
+    pub fn range(&self) -> std::ops::RangeInclusive<f32> {
+        match self {
+            FilterKind::Brightness => -1.0..=1.0,
+            FilterKind::Contrast | FilterKind::Saturation => 0.0..=3.0,
+            FilterKind::Gamma => 0.1..=5.0,
+            FilterKind::HueRotate => -180.0..=180.0,
+            FilterKind::Invert
+            | FilterKind::Grayscale
+            | FilterKind::Sepia
+            | FilterKind::Threshold => 0.0..=1.0,
+        }
+    }
+
+    /// What `amount` starts at when the filter is added.
+    pub fn default_amount(&self) -> f32 {
+        match self {
+            FilterKind::Brightness => 0.1,
+            FilterKind::Contrast | FilterKind::Saturation => 1.2,
+            FilterKind::Gamma => 1.2,
+            FilterKind::HueRotate => 90.0,
+            FilterKind::Invert | FilterKind::Grayscale | FilterKind::Sepia => 1.0,
+            FilterKind::Threshold => 0.5,
+        }
+    }
+}
+
+/// One filter in the processing chain.
+///
+/// Each kind has a single parameter: an offset for brightness, a factor for contrast and
+/// saturation, the gamma itself, an angle in degrees for hue rotate, the cut off luma for
+/// threshold, and how much of the effect to mix in for the rest.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct FilterStep {
+    pub kind: FilterKind,
+    pub amount: f32,
+    /// switched off steps stay in the chain but are skipped
+    pub enabled: bool,
+}
+
+impl Default for FilterStep {
+    fn default() -> Self {
+        FilterKind::Brightness.into()
+    }
+}
+
+impl From<FilterKind> for FilterStep {
+    fn from(kind: FilterKind) -> Self {
+        FilterStep {
+            kind,
+            amount: kind.default_amount(),
+            enabled: true,
+        }
+    }
+}
+
+/// The enabled steps of a chain, like "contrast 1.20, sepia 1.00".
+pub fn describe(filters: &[FilterStep]) -> String {
+    filters
+        .iter()
+        .filter(|step| step.enabled)
+        .map(|step| format!("{} {:.2}", step.kind.name(), step.amount))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl Filter for FilterStep {
+    fn pixel(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let amount = self.amount;
+        match self.kind {
+            FilterKind::Brightness => rgb.map(|c| c + amount),
+            FilterKind::Contrast => rgb.map(|c| (c - 0.5) * amount + 0.5),
+            FilterKind::Saturation => mix([luma(rgb); 3], rgb, amount),
+            FilterKind::Gamma => rgb.map(|c| c.max(0.0).powf(1.0 / amount.max(0.01))),
+            FilterKind::HueRotate => mul(hue_matrix(amount), rgb),
+            FilterKind::Invert => mix(rgb, rgb.map(|c| 1.0 - c), amount),
+            FilterKind::Grayscale => mix(rgb, [luma(rgb); 3], amount),
+            FilterKind::Sepia => mix(rgb, mul(SEPIA, rgb), amount),
+            FilterKind::Threshold => [if luma(rgb) >= amount { 1.0 } else { 0.0 }; 3],
+        }
+    }
+}
+
+/// A chain of filters, applied in order in a single sweep over the image.
+impl Filter for [FilterStep] {
+    fn pixel(&self, rgb: [f32; 3]) -> [f32; 3] {
+        self.iter()
+            .filter(|step| step.enabled)
+            .fold(rgb, |rgb, step| step.pixel(rgb).map(|c| c.clamp(0.0, 1.0)))
+    }
+}
+
+impl Pass for FilterStep {
+    fn fragment(&self) -> String {
+        let body = match self.kind {
+            FilterKind::Brightness => "c.rgb + amount",
+            FilterKind::Contrast => "(c.rgb - 0.5) * amount + 0.5",
+            FilterKind::Saturation => "mix(vec3(dot(c.rgb, LUMA)), c.rgb, amount)",
+            FilterKind::Gamma => "pow(max(c.rgb, 0.0), vec3(1.0 / max(amount, 0.01)))",
+            FilterKind::HueRotate => "hue_rotate(radians(amount)) * c.rgb",
+            FilterKind::Invert => "mix(c.rgb, 1.0 - c.rgb, amount)",
+            FilterKind::Grayscale => "mix(c.rgb, vec3(dot(c.rgb, LUMA)), amount)",
+            FilterKind::Sepia => "mix(c.rgb, SEPIA * c.rgb, amount)",
+            FilterKind::Threshold => "vec3(step(amount, dot(c.rgb, LUMA)))",
+        };
+        format!(
+            r#"
+    uniform float amount;
+    const vec3 LUMA = vec3({}, {}, {});
+    // glsl matrices are column major, so these read transposed
+    const mat3 SEPIA = mat3(0.393, 0.349, 0.272, 0.769, 0.686, 0.534, 0.189, 0.168, 0.131);
+    mat3 hue_rotate(float a) {{
+        float s = sin(a);
+        float k = cos(a);
+        return mat3(
+            0.213 + k * 0.787 - s * 0.213, 0.213 - k * 0.213 + s * 0.143, 0.213 - k * 0.213 - s * 0.787,
+            0.715 - k * 0.715 - s * 0.715, 0.715 + k * 0.285 + s * 0.140, 0.715 - k * 0.715 + s * 0.715,
+            0.072 - k * 0.072 + s * 0.928, 0.072 - k * 0.072 - s * 0.283, 0.072 + k * 0.928 + s * 0.072
+        );
+    }}
+    void main() {{
+        vec4 c = texture(u_image, v_uv);
+        color = vec4(clamp({body}, 0.0, 1.0), c.a);
+    }}
+"#,
+            LUMA[0], LUMA[1], LUMA[2]
+        )
+    }
+
+    fn uniforms(&self) -> Vec<(&'static str, f32)> {
+        vec![("amount", self.amount)]
+    }
+
+    fn apply_cpu(&self, img: &mut MyImage) {
+        self.apply(img);
+    }
+}
+
+impl MyApp {
+    pub fn render_filters_ui(&mut self, ui: &mut Ui) {
+        render_filter_chain(ui, &mut self.filters);
+    }
+}
+
+/// Add/remove/reorder controls and a slider for every step of `filters`.
+pub fn render_filter_chain(ui: &mut Ui, filters: &mut Vec<FilterStep>) {
+    ui.label(RichText::new("add filter").strong());
+    ui.horizontal_wrapped(|ui| {
+        for i in FilterKind::iter() {
+            if ui.small_button(i.name()).clicked() {
+                filters.push(i.into());
+            }
+        }
+    });
+    ui.separator();
+
+    if filters.is_empty() {
+        ui.label("no filters");
+        return;
+    }
+
+    let len = filters.len();
+    let mut remove = None;
+    let mut swap = None;
+    for (i, step) in filters.iter_mut().enumerate() {
+        ui.push_id(i, |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut step.enabled, RichText::new(step.kind.name()).strong());
+                if ui
+                    .add_enabled(i > 0, egui::Button::new(ARROW_UP).small())
+                    .clicked()
+                {
+                    swap = Some(i - 1);
+                }
+                if ui
+                    .add_enabled(i + 1 < len, egui::Button::new(ARROW_DOWN).small())
+                    .clicked()
+                {
+                    swap = Some(i);
+                }
+                if ui.small_button(X).clicked() {
+                    remove = Some(i);
+                }
+            });
+            ui.add_enabled(
+                step.enabled,
+                egui::Slider::new(&mut step.amount, step.kind.range()),
+            );
+        });
+    }
+    if let Some(i) = swap {
+        filters.swap(i, i + 1);
+    }
+    if let Some(i) = remove {
+        filters.remove(i);
+    }
+
+    ui.separator();
+    if ui.button("remove all").clicked() {
+        filters.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A few pixels covering black, white, the primaries and a grey, all fully opaque
+    /// except the last so alpha being left alone shows up.
+    fn pixels() -> MyImage {
+        let bytes = [
+            [0, 0, 0, 255],
+            [255, 255, 255, 255],
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [0, 0, 255, 255],
+            [100, 150, 200, 77],
+        ];
+        MyImage {
+            width: bytes.len() as u32,
+            height: 1,
+            bytes: bytes.concat(),
+            ..Default::default()
+        }
+    }
+
+    fn step(kind: FilterKind, amount: f32) -> FilterStep {
+        FilterStep {
+            kind,
+            amount,
+            enabled: true,
+        }
+    }
+
+    fn applied(filters: &[FilterStep]) -> Vec<[u8; 4]> {
+        let mut img = pixels();
+        filters.apply(&mut img);
+        img.bytes
+            .chunks_exact(4)
+            .map(|px| [px[0], px[1], px[2], px[3]])
+            .collect()
+    }
+
+    fn original() -> Vec<[u8; 4]> {
+        applied(&[])
+    }
+
+    #[test]
+    fn invert() {
+        let inverted = applied(&[step(FilterKind::Invert, 1.0)]);
+        assert_eq!(inverted[0], [255, 255, 255, 255]);
+        assert_eq!(inverted[2], [0, 255, 255, 255]);
+        assert_eq!(inverted[5], [155, 105, 55, 77]);
+        // halfway is grey whatever the colour
+        assert!(applied(&[step(FilterKind::Invert, 0.5)])
+            .iter()
+            .all(|px| px[..3] == [128; 3]));
+        assert_eq!(applied(&[step(FilterKind::Invert, 0.0)]), original());
+    }
+
+    #[test]
+    fn threshold() {
+        let black_white = applied(&[step(FilterKind::Threshold, 0.5)]);
+        let expected = [[0; 3], [255; 3], [0; 3], [255; 3], [0; 3], [255; 3]];
+        assert_eq!(
+            black_white
+                .iter()
+                .map(|px| [px[0], px[1], px[2]])
+                .collect::<Vec<_>>(),
+            expected
+        );
+        assert_eq!(black_white[5][3], 77);
+        // everything is at least as bright as 0
+        assert!(applied(&[step(FilterKind::Threshold, 0.0)])
+            .iter()
+            .all(|px| px[..3] == [255; 3]));
+    }
+
+    #[test]
+    fn no_hue_rotation_changes_nothing() {
+        assert_eq!(applied(&[step(FilterKind::HueRotate, 0.0)]), original());
+        assert_eq!(applied(&[step(FilterKind::HueRotate, 360.0)]), original());
+        assert_ne!(applied(&[step(FilterKind::HueRotate, 90.0)]), original());
+    }
+
+    #[test]
+    fn no_brightness_changes_nothing() {
+        assert_eq!(applied(&[step(FilterKind::Brightness, 0.0)]), original());
+        assert_eq!(
+            applied(&[step(FilterKind::Brightness, 1.0)])[0],
+            [255, 255, 255, 255]
+        );
+        assert_eq!(
+            applied(&[step(FilterKind::Brightness, -1.0)])[1],
+            [0, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn neutral_amounts_change_nothing() {
+        for filter in [
+            step(FilterKind::Contrast, 1.0),
+            step(FilterKind::Saturation, 1.0),
+            step(FilterKind::Gamma, 1.0),
+            step(FilterKind::Grayscale, 0.0),
+            step(FilterKind::Sepia, 0.0),
+        ] {
+            assert_eq!(applied(&[filter]), original(), "{filter:?}");
+        }
+    }
+
+    #[test]
+    fn chain_clamps_after_every_step() {
+        let up_down = [
+            step(FilterKind::Brightness, 0.5),
+            step(FilterKind::Brightness, -0.5),
+        ];
+        // white is clipped at 1 before coming back down, rather than ending up white again
+        assert_eq!(applied(&up_down)[1], [128, 128, 128, 255]);
+        let down_up = [
+            step(FilterKind::Brightness, -0.5),
+            step(FilterKind::Brightness, 0.5),
+        ];
+        assert_eq!(applied(&down_up)[0], [128, 128, 128, 255]);
+        // and the order of the steps matters
+        let brighten_invert = [
+            step(FilterKind::Brightness, 0.5),
+            step(FilterKind::Invert, 1.0),
+        ];
+        let invert_brighten = [
+            step(FilterKind::Invert, 1.0),
+            step(FilterKind::Brightness, 0.5),
+        ];
+        assert_eq!(applied(&brighten_invert)[0], [128, 128, 128, 255]);
+        assert_eq!(applied(&invert_brighten)[0], [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn disabled_steps_are_skipped() {
+        let off = FilterStep {
+            enabled: false,
+            ..step(FilterKind::Invert, 1.0)
+        };
+        assert_eq!(applied(&[off]), original());
+        assert_eq!(describe(&[off, step(FilterKind::Gamma, 2.0)]), "gamma 2.00");
+    }
+}
@@ -0,0 +1,342 @@
+use strum_macros::EnumIter;
+
+/// Which way the camera should face when no specific device has been picked.
+#[derive(serde::Deserialize, serde::Serialize, EnumIter, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Facing {
+    Environment,
+    User,
+    Any,
+}
+
+impl Facing {
+    pub fn name(&self) -> &str {
+        match self {
+            Facing::Environment => "back",
+            Facing::User => "front",
+            Facing::Any => "any",
+        }
+    }
+}
+
+/// What to ask `getUserMedia` for.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone)]
+#[serde(default)]
+pub struct CameraOptions {
+    pub facing: Facing,
+    /// a specific camera, overrides `facing` when not empty
+    pub device_id: String,
+    /// the browser gets as close as it can to these, they aren't hard requirements
+    pub size: [u32; 2],
+    pub frame_rate: f32,
+}
+
+impl Default for CameraOptions {
+    fn default() -> Self {
+        CameraOptions {
+            facing: Facing::Environment,
+            device_id: String::new(),
+            size: [1920, 1080],
+            frame_rate: 30.0,
+        }
+    }
+}
+
+/// A camera the browser told us about.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraDevice {
+    pub id: String,
+    pub label: String,
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use web::{camera_devices, camera_error, refresh_camera_devices, HtmlVideoSource};
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use std::cell::RefCell;
+
+    use anyhow::anyhow;
+    use chrono::{DateTime, Local};
+    use js_sys::{Array, Object, Reflect};
+    use wasm_bindgen::{JsCast, JsValue};
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{
+        window, MediaDeviceInfo, MediaDeviceKind, MediaStream, MediaStreamConstraints,
+        MediaStreamTrack,
+    };
+    use web_time::Instant;
+
+    use crate::{
+        frame_source::{Frame, FrameSource, Resolution},
+        image::MyImage,
+    };
+
+    use super::{CameraDevice, CameraOptions, Facing};
+
+    thread_local! {
+        // filled in by promises, which have nowhere else to put their results
+        static DEVICES: RefCell<Vec<CameraDevice>> = const { RefCell::new(vec![]) };
+        static ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+    }
+
+    /// The video inputs found by the last [`refresh_camera_devices`].
+    pub fn camera_devices() -> Vec<CameraDevice> {
+        DEVICES.with_borrow(|d| d.clone())
+    }
+
+    /// Why the last attempt to open the camera failed, if it did.
+    pub fn camera_error() -> Option<String> {
+        ERROR.with_borrow(|e| e.clone())
+    }
+
+    fn set_error(e: Option<String>) {
+        ERROR.with_borrow_mut(|error| *error = e);
+    }
+
+    fn js_error(e: JsValue) -> anyhow::Error {
+        anyhow!(
+            "{}",
+            Reflect::get(&e, &"name".into())
+                .ok()
+                .and_then(|n| n.as_string())
+                .unwrap_or(format!("{e:?}"))
+        )
+    }
+
+    fn video_element() -> anyhow::Result<web_sys::HtmlVideoElement> {
+        window()
+            .ok_or(anyhow!("no window"))?
+            .document()
+            .ok_or(anyhow!("no document"))?
+            .get_element_by_id("videoElement")
+            .ok_or(anyhow!("video element not found"))?
+            .dyn_into::<web_sys::HtmlVideoElement>()
+            .map_err(|_| anyhow!("videoElement is not a video"))
+    }
+
+    /// Asks the browser for its cameras again. Labels are only filled in once the user
+    /// has allowed camera access, so this is also done after every stream starts.
+    pub fn refresh_camera_devices() {
+        wasm_bindgen_futures::spawn_local(async {
+            let devices = async {
+                let media = window()
+                    .ok_or(anyhow!("no window"))?
+                    .navigator()
+                    .media_devices()
+                    .map_err(js_error)?;
+                let list = JsFuture::from(media.enumerate_devices().map_err(js_error)?)
+                    .await
+                    .map_err(js_error)?;
+                anyhow::Ok(
+                    Array::from(&list)
+                        .iter()
+                        .map(|d| d.unchecked_into::<MediaDeviceInfo>())
+                        .filter(|d| d.kind() == MediaDeviceKind::Videoinput)
+                        .enumerate()
+                        .map(|(i, d)| CameraDevice {
+                            id: d.device_id(),
+                            label: Some(d.label())
+                                .filter(|l| !l.is_empty())
+                                .unwrap_or(format!("camera {}", i + 1)),
+                        })
+                        .collect::<Vec<CameraDevice>>(),
+                )
+            }
+            .await;
+            match devices {
+                Ok(devices) => DEVICES.with_borrow_mut(|d| *d = devices),
+                Err(e) => log::warn!("failed to list cameras: {e:?}"),
+            }
+        });
+    }
+
+    fn ideal(value: f64) -> JsValue {
+        let o = Object::new();
+        Reflect::set(&o, &"ideal".into(), &value.into()).ok();
+        o.into()
+    }
+
+    /// Builds the `{ video: { ... } }` object passed to `getUserMedia`.
+    fn constraints(options: &CameraOptions) -> MediaStreamConstraints {
+        let video = Object::new();
+        if !options.device_id.is_empty() {
+            let exact = Object::new();
+            Reflect::set(&exact, &"exact".into(), &options.device_id.as_str().into()).ok();
+            Reflect::set(&video, &"deviceId".into(), &exact).ok();
+        } else if options.facing != Facing::Any {
+            let mode = match options.facing {
+                Facing::User => "user",
+                _ => "environment",
+            };
+            let ideal = Object::new();
+            Reflect::set(&ideal, &"ideal".into(), &mode.into()).ok();
+            Reflect::set(&video, &"facingMode".into(), &ideal).ok();
+        }
+        Reflect::set(&video, &"width".into(), &ideal(options.size[0] as f64)).ok();
+        Reflect::set(&video, &"height".into(), &ideal(options.size[1] as f64)).ok();
+        Reflect::set(
+            &video,
+            &"frameRate".into(),
+            &ideal(options.frame_rate as f64),
+        )
+        .ok();
+
+        let constraints = Object::new();
+        Reflect::set(&constraints, &"video".into(), &video).ok();
+        constraints.unchecked_into()
+    }
+
+    fn stop_stream(video: &web_sys::HtmlVideoElement) {
+        if let Some(stream) = video.src_object() {
+            for track in stream.get_tracks().iter() {
+                track.unchecked_into::<MediaStreamTrack>().stop();
+            }
+        }
+        video.set_src_object(None);
+    }
+
+    async fn open_stream(options: CameraOptions) -> anyhow::Result<()> {
+        let media = window()
+            .ok_or(anyhow!("no window"))?
+            .navigator()
+            .media_devices()
+            .map_err(js_error)?;
+        let request = |c: &MediaStreamConstraints| {
+            media.get_user_media_with_constraints(c).map(JsFuture::from)
+        };
+        let stream = match request(&constraints(&options)).map_err(js_error)?.await {
+            Ok(stream) => stream,
+            // the device might have been unplugged, or not like the constraints, take any camera instead
+            Err(e) => {
+                log::warn!(
+                    "camera request failed, trying any camera: {:?}",
+                    js_error(e)
+                );
+                let any = Object::new();
+                Reflect::set(&any, &"video".into(), &true.into()).ok();
+                request(any.unchecked_ref())
+                    .map_err(js_error)?
+                    .await
+                    .map_err(js_error)?
+            }
+        };
+        let video = video_element()?;
+        stop_stream(&video);
+        video.set_src_object(Some(stream.unchecked_ref::<MediaStream>()));
+        Ok(())
+    }
+
+    /// Grabs frames from the `videoElement` in `index.html` by drawing it onto the hidden `canvas`.
+    pub struct HtmlVideoSource {
+        options: CameraOptions,
+        started: Instant,
+    }
+
+    impl HtmlVideoSource {
+        pub fn new(options: CameraOptions) -> Self {
+            HtmlVideoSource {
+                options,
+                started: Instant::now(),
+            }
+        }
+    }
+
+    impl FrameSource for HtmlVideoSource {
+        fn name(&self) -> String {
+            // the label of the camera actually streaming, which isn't always the one asked for
+            video_element()
+                .ok()
+                .and_then(|video| video.src_object())
+                .and_then(|stream| stream.get_video_tracks().iter().next())
+                .map(|track| track.unchecked_into::<MediaStreamTrack>().label())
+                .filter(|label| !label.is_empty())
+                .map_or("camera".to_owned(), |label| format!("camera ({label})"))
+        }
+
+        fn start(&mut self) -> anyhow::Result<()> {
+            self.started = Instant::now();
+            set_error(None);
+            let options = self.options.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match open_stream(options).await {
+                    Ok(()) => refresh_camera_devices(),
+                    Err(e) => set_error(Some(e.to_string())),
+                }
+            });
+            Ok(())
+        }
+
+        fn stop(&mut self) {
+            if let Ok(video) = video_element() {
+                stop_stream(&video);
+            }
+        }
+
+        fn next_frame(&mut self, resolution: Resolution) -> anyhow::Result<Frame> {
+            let document = window()
+                .ok_or(anyhow!("no window"))?
+                .document()
+                .ok_or(anyhow!("no document"))?;
+            let video = video_element()?;
+            let canvas: web_sys::HtmlCanvasElement = document
+                .get_element_by_id("canvas")
+                .ok_or(anyhow!("canvas element not found"))?
+                .dyn_into::<web_sys::HtmlCanvasElement>()
+                .map_err(|_| anyhow!("canvas is not a canvas"))?;
+
+            // the size of the stream itself, not of the (hidden) element showing it
+            let native = [video.video_width(), video.video_height()];
+            if native.contains(&0) {
+                return Err(anyhow!(
+                    camera_error().unwrap_or("waiting for the camera".to_owned())
+                ));
+            }
+            let [width, height] = resolution.fit(native);
+            canvas.set_width(width);
+            canvas.set_height(height);
+
+            let context = canvas
+                .get_context("2d")
+                .ok()
+                .flatten()
+                .ok_or(anyhow!("failed to get 2d context"))?
+                .dyn_into::<web_sys::CanvasRenderingContext2d>()
+                .map_err(|_| anyhow!("failed to get 2d context"))?;
+
+            context
+                .draw_image_with_html_video_element_and_dw_and_dh(
+                    &video,
+                    0.0,
+                    0.0,
+                    width as f64,
+                    height as f64,
+                )
+                .ok()
+                .ok_or(anyhow!("failed to write image"))?;
+
+            let data = context
+                .get_image_data(0.0, 0.0, width as f64, height as f64)
+                .ok()
+                .ok_or(anyhow!("failed to capture"))?
+                .data()
+                .to_vec();
+            if data.len() != MyImage::rgba_len(width, height) {
+                return Err(anyhow!(
+                    "captured {} bytes for a {width}x{height} frame",
+                    data.len()
+                ));
+            }
+            let local: DateTime<Local> = Local::now();
+            Ok(Frame {
+                image: MyImage {
+                    name: local.format("%Y-%m-%d %H-%M-%S").to_string(),
+                    width,
+                    height,
+                    bytes: data,
+                    ..Default::default()
+                },
+                timestamp: self.started.elapsed(),
+            })
+        }
+    }
+}
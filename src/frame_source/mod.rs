@@ -0,0 +1,480 @@
+use anyhow::anyhow;
+use egui::{Color32, RichText, Ui};
+use egui_phosphor::regular::{CARET_LEFT, CARET_RIGHT, PAUSE, PLAY};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+use web_time::Duration;
+
+use crate::{image::MyImage, MyApp};
+
+// only the options are needed outside the browser, so they can still be persisted
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+mod html_video;
+mod playback;
+mod test_pattern;
+// only the options are needed off linux, so they can still be persisted
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+mod v4l2;
+
+pub use html_video::CameraOptions;
+#[cfg(target_arch = "wasm32")]
+pub use html_video::{
+    camera_devices, camera_error, refresh_camera_devices, Facing, HtmlVideoSource,
+};
+pub use playback::{PlaybackOptions, PlaybackSource};
+pub use test_pattern::{TestPattern, TestPatternOptions, TestPatternSource};
+pub use v4l2::V4l2Options;
+#[cfg(target_os = "linux")]
+pub use v4l2::{list_devices, DeviceInfo, PixelFormat, V4l2Source};
+
+/// A single captured frame, along with how long after `start` it was taken.
+pub struct Frame {
+    pub image: MyImage,
+    pub timestamp: Duration,
+}
+
+/// Anything that can feed images into the app.
+///
+/// `MyApp` only ever talks to a boxed `FrameSource`, so the browser camera,
+/// generated test patterns and recorded footage can all be swapped in.
+pub trait FrameSource {
+    /// Short human readable name, shown in the ui.
+    fn name(&self) -> String;
+    fn start(&mut self) -> anyhow::Result<()>;
+    fn stop(&mut self);
+    /// Frames come back at `resolution`, with the aspect ratio of the source.
+    fn next_frame(&mut self, resolution: Resolution) -> anyhow::Result<Frame>;
+
+    /// For recorded footage: `(current frame, number of frames)`.
+    fn position(&self) -> Option<(usize, usize)> {
+        None
+    }
+    /// For recorded footage: stay on `Some(frame)`, or carry on playing from there with `None`.
+    fn hold(&mut self, _frame: Option<usize>) {}
+}
+
+/// How big a captured frame should be, as the length of its shorter side.
+#[derive(serde::Deserialize, serde::Serialize, EnumIter, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Resolution {
+    Full,
+    P2160,
+    P1080,
+    P720,
+    P480,
+    P360,
+    P240,
+}
+
+impl Resolution {
+    pub fn name(&self) -> &str {
+        match self {
+            Resolution::Full => "full",
+            Resolution::P2160 => "2160p",
+            Resolution::P1080 => "1080p",
+            Resolution::P720 => "720p",
+            Resolution::P480 => "480p",
+            Resolution::P360 => "360p",
+            Resolution::P240 => "240p",
+        }
+    }
+
+    pub fn short_side(&self) -> Option<u32> {
+        match self {
+            Resolution::Full => None,
+            Resolution::P2160 => Some(2160),
+            Resolution::P1080 => Some(1080),
+            Resolution::P720 => Some(720),
+            Resolution::P480 => Some(480),
+            Resolution::P360 => Some(360),
+            Resolution::P240 => Some(240),
+        }
+    }
+
+    /// The size to capture a `[width, height]` frame at: scaled down (never up) until the
+    /// shorter side fits, keeping the aspect ratio.
+    pub fn fit(&self, size: [u32; 2]) -> [u32; 2] {
+        let [width, height] = size;
+        match self.short_side() {
+            Some(short) if width.min(height) > short => {
+                let scale = short as f64 / width.min(height) as f64;
+                [
+                    ((width as f64 * scale).round() as u32).max(1),
+                    ((height as f64 * scale).round() as u32).max(1),
+                ]
+            }
+            _ => size,
+        }
+    }
+}
+
+/// Limited range bt.601, which is what webcams and y4m files almost always are.
+pub fn yuv_to_rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+    let l = (y as f32 - 16.0) * 1.164;
+    let u = u as f32 - 128.0;
+    let v = v as f32 - 128.0;
+    [
+        (l + 1.596 * v) as u8,
+        (l - 0.392 * u - 0.813 * v) as u8,
+        (l + 2.017 * u) as u8,
+    ]
+}
+
+/// Used when there is nothing to capture from (e.g. the native build).
+pub struct NoSource;
+
+impl FrameSource for NoSource {
+    fn name(&self) -> String {
+        "none".to_owned()
+    }
+    fn start(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+    fn stop(&mut self) {}
+    fn next_frame(&mut self, _resolution: Resolution) -> anyhow::Result<Frame> {
+        Err(anyhow!("no frame source available"))
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, EnumIter, PartialEq, Eq, Clone, Copy)]
+pub enum SourceKind {
+    Camera,
+    TestPattern,
+    Playback,
+    V4l2,
+}
+
+impl Default for SourceKind {
+    fn default() -> Self {
+        // the v4l2 source needs a device that may not be there, the test pattern always works
+        if cfg!(target_arch = "wasm32") {
+            SourceKind::Camera
+        } else {
+            SourceKind::TestPattern
+        }
+    }
+}
+
+impl SourceKind {
+    fn name(&self) -> &str {
+        match self {
+            SourceKind::Camera => "camera",
+            SourceKind::TestPattern => "test pattern",
+            SourceKind::Playback => "playback",
+            SourceKind::V4l2 => "v4l2",
+        }
+    }
+
+    /// Playback reads from the filesystem, which the browser doesn't have.
+    fn available(&self) -> bool {
+        match self {
+            SourceKind::Playback => !cfg!(target_arch = "wasm32"),
+            SourceKind::V4l2 => cfg!(target_os = "linux"),
+            _ => true,
+        }
+    }
+}
+
+/// Which source to use and how it is set up, persisted with the rest of `MyApp`.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone)]
+#[serde(default)]
+pub struct SourceOptions {
+    pub kind: SourceKind,
+    pub camera: CameraOptions,
+    pub test_pattern: TestPatternOptions,
+    pub playback: PlaybackOptions,
+    pub v4l2: V4l2Options,
+    /// what is shown live while framing a shot
+    pub preview: Resolution,
+    /// what photos are taken at
+    pub photo: Resolution,
+}
+
+impl Default for SourceOptions {
+    fn default() -> Self {
+        SourceOptions {
+            kind: SourceKind::default(),
+            camera: CameraOptions::default(),
+            test_pattern: TestPatternOptions::default(),
+            playback: PlaybackOptions::default(),
+            v4l2: V4l2Options::default(),
+            preview: Resolution::P360,
+            photo: Resolution::Full,
+        }
+    }
+}
+
+impl SourceOptions {
+    pub fn build(&self) -> Box<dyn FrameSource> {
+        match self.kind {
+            #[cfg(target_arch = "wasm32")]
+            SourceKind::Camera => Box::new(HtmlVideoSource::new(self.camera.clone())),
+            #[cfg(not(target_arch = "wasm32"))]
+            SourceKind::Camera => Box::new(NoSource),
+            SourceKind::TestPattern => Box::new(TestPatternSource::new(self.test_pattern.clone())),
+            SourceKind::Playback => Box::new(PlaybackSource::new(self.playback.clone())),
+            #[cfg(target_os = "linux")]
+            SourceKind::V4l2 => Box::new(V4l2Source::new(self.v4l2.clone())),
+            #[cfg(not(target_os = "linux"))]
+            SourceKind::V4l2 => Box::new(NoSource),
+        }
+    }
+}
+
+impl MyApp {
+    /// Stops the current source and replaces it with one built from `source_options`.
+    pub fn restart_source(&mut self) {
+        self.source.stop();
+        self.source = self.source_options.build();
+        // don't leave the last frame of the old source frozen on screen
+        self.texture = None;
+        self.source_error = self.source.start().err().map(|e| {
+            log::warn!("failed to start {}: {e:?}", self.source.name());
+            e.to_string()
+        });
+    }
+
+    pub fn render_source_ui(&mut self, ui: &mut Ui) {
+        let old = self.source_options.clone();
+        ui.horizontal_wrapped(|ui| {
+            for i in SourceKind::iter().filter(SourceKind::available) {
+                ui.add_enabled_ui(i != self.source_options.kind, |ui| {
+                    if ui.small_button(i.name()).clicked() {
+                        self.source_options.kind = i;
+                    }
+                });
+            }
+        });
+
+        for (label, resolution) in [
+            ("preview", &mut self.source_options.preview),
+            ("photo", &mut self.source_options.photo),
+        ] {
+            egui::ComboBox::from_label(label)
+                .selected_text(resolution.name())
+                .show_ui(ui, |ui| {
+                    for i in Resolution::iter() {
+                        ui.selectable_value(resolution, i, i.name());
+                    }
+                });
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        if self.source_options.kind == SourceKind::Camera {
+            render_camera_ui(&mut self.source_options.camera, ui);
+        }
+
+        if self.source_options.kind == SourceKind::TestPattern {
+            let options = &mut self.source_options.test_pattern;
+            egui::ComboBox::from_label("pattern")
+                .selected_text(options.pattern.name())
+                .show_ui(ui, |ui| {
+                    for i in TestPattern::iter() {
+                        ui.selectable_value(&mut options.pattern, i, i.name());
+                    }
+                });
+            ui.add(egui::Slider::new(&mut options.width, 16..=3840).text("width"));
+            ui.add(egui::Slider::new(&mut options.height, 16..=2160).text("height"));
+            ui.add(egui::Slider::new(&mut options.fps, 1.0..=120.0).text("fps"));
+            ui.checkbox(&mut options.burn_in, "frame counter");
+        }
+
+        if self.source_options.kind == SourceKind::Playback {
+            let options = &mut self.source_options.playback;
+            // only reload once the path has been typed out, not on every keystroke
+            let id = ui.make_persistent_id("playback path");
+            let mut path = ui
+                .data_mut(|d| d.get_temp::<String>(id))
+                .unwrap_or_else(|| options.path.clone());
+            ui.horizontal(|ui| {
+                ui.label("path");
+                if ui.text_edit_singleline(&mut path).lost_focus() {
+                    options.path = path.clone();
+                }
+            });
+            ui.data_mut(|d| d.insert_temp(id, path));
+            ui.checkbox(&mut options.looping, "loop");
+            ui.add(egui::Slider::new(&mut options.fps, 1.0..=60.0).text("fps"));
+
+            if let Some((current, len)) = self.source.position() {
+                ui.horizontal(|ui| {
+                    if ui.button(CARET_LEFT).clicked() {
+                        self.source.hold(Some(current.saturating_sub(1)));
+                    }
+                    ui.label(RichText::new(format!("{}/{}", current + 1, len)).strong());
+                    if ui.button(CARET_RIGHT).clicked() {
+                        self.source.hold(Some(current + 1));
+                    }
+                    if ui.button(PLAY).clicked() {
+                        self.source.hold(None);
+                    }
+                    if ui.button(PAUSE).clicked() {
+                        self.source.hold(Some(current));
+                    }
+                });
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        if self.source_options.kind == SourceKind::V4l2 {
+            self.render_v4l2_ui(ui);
+        }
+
+        if let Some(e) = &self.source_error {
+            ui.label(RichText::new(e).color(Color32::RED));
+        }
+
+        // the resolutions are only read when capturing, no need to restart for them
+        let restart = SourceOptions {
+            preview: old.preview,
+            photo: old.photo,
+            ..self.source_options.clone()
+        } != old;
+        if restart {
+            self.restart_source();
+        }
+    }
+}
+
+impl MyApp {
+    #[cfg(target_os = "linux")]
+    fn render_v4l2_ui(&mut self, ui: &mut Ui) {
+        // opening every device is slow, so only do it when asked to
+        let id = ui.make_persistent_id("v4l2 devices");
+        let devices = match ui.data_mut(|d| d.get_temp::<Vec<DeviceInfo>>(id)) {
+            Some(devices) if !ui.small_button("refresh devices").clicked() => devices,
+            _ => {
+                // the open device can't be queried while it is streaming
+                self.source.stop();
+                let devices = list_devices();
+                ui.data_mut(|d| d.insert_temp(id, devices.clone()));
+                self.restart_source();
+                devices
+            }
+        };
+
+        let options = &mut self.source_options.v4l2;
+        let selected = devices.iter().find(|d| d.path == options.device);
+        egui::ComboBox::from_label("device")
+            .selected_text(selected.map_or(options.device.clone(), |d| d.name.clone()))
+            .show_ui(ui, |ui| {
+                for d in &devices {
+                    ui.selectable_value(
+                        &mut options.device,
+                        d.path.clone(),
+                        format!("{} ({})", d.name, d.path),
+                    );
+                }
+            });
+        egui::ComboBox::from_label("format")
+            .selected_text(options.format.name())
+            .show_ui(ui, |ui| {
+                for f in PixelFormat::iter() {
+                    ui.selectable_value(&mut options.format, f, f.name());
+                }
+            });
+
+        let mut sizes = selected
+            .iter()
+            .flat_map(|d| &d.formats)
+            .filter(|f| options.format == PixelFormat::Auto || f.format == options.format)
+            .flat_map(|f| f.sizes.iter().copied())
+            .collect::<Vec<[u32; 2]>>();
+        sizes.sort_by_key(|s| s[0] * s[1]);
+        sizes.dedup();
+        egui::ComboBox::from_label("resolution")
+            .selected_text(format!("{}x{}", options.size[0], options.size[1]))
+            .show_ui(ui, |ui| {
+                for s in sizes {
+                    ui.selectable_value(&mut options.size, s, format!("{}x{}", s[0], s[1]));
+                }
+            });
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn render_camera_ui(options: &mut CameraOptions, ui: &mut Ui) {
+    let devices = camera_devices();
+    ui.horizontal_wrapped(|ui| {
+        for i in Facing::iter() {
+            let selected = options.device_id.is_empty() && options.facing == i;
+            ui.add_enabled_ui(!selected, |ui| {
+                if ui.small_button(i.name()).clicked() {
+                    options.facing = i;
+                    options.device_id.clear();
+                }
+            });
+        }
+    });
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_label("device")
+            .selected_text(
+                devices
+                    .iter()
+                    .find(|d| d.id == options.device_id)
+                    .map_or("by direction", |d| d.label.as_str()),
+            )
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut options.device_id, String::new(), "by direction");
+                for d in &devices {
+                    ui.selectable_value(&mut options.device_id, d.id.clone(), &d.label);
+                }
+            });
+        if ui
+            .small_button(egui_phosphor::regular::ARROW_CLOCKWISE)
+            .clicked()
+        {
+            refresh_camera_devices();
+        }
+    });
+    egui::ComboBox::from_label("stream size")
+        .selected_text(format!("{}x{}", options.size[0], options.size[1]))
+        .show_ui(ui, |ui| {
+            for s in [[640, 480], [1280, 720], [1920, 1080], [3840, 2160]] {
+                ui.selectable_value(&mut options.size, s, format!("{}x{}", s[0], s[1]));
+            }
+        });
+    ui.add(egui::Slider::new(&mut options.frame_rate, 5.0..=60.0).text("fps"));
+    if let Some(e) = camera_error() {
+        ui.label(RichText::new(e).color(Color32::RED));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_landscape() {
+        assert_eq!(Resolution::P720.fit([1920, 1080]), [1280, 720]);
+        assert_eq!(Resolution::P480.fit([1920, 1080]), [853, 480]);
+    }
+
+    #[test]
+    fn fit_portrait() {
+        // the short side is the width, the height must scale with it rather than match it
+        assert_eq!(Resolution::P720.fit([1080, 1920]), [720, 1280]);
+        assert_eq!(Resolution::P240.fit([480, 640]), [240, 320]);
+    }
+
+    #[test]
+    fn fit_full_keeps_the_size() {
+        assert_eq!(Resolution::Full.fit([4000, 3000]), [4000, 3000]);
+        assert_eq!(Resolution::Full.fit([3000, 4000]), [3000, 4000]);
+    }
+
+    #[test]
+    fn fit_never_upscales() {
+        assert_eq!(Resolution::P1080.fit([640, 480]), [640, 480]);
+        assert_eq!(Resolution::P480.fit([480, 640]), [480, 640]);
+        assert_eq!(Resolution::P2160.fit([1, 1]), [1, 1]);
+    }
+
+    #[test]
+    fn fit_rounds_to_the_nearest_pixel() {
+        assert_eq!(Resolution::P720.fit([1366, 768]), [1281, 720]);
+        assert_eq!(Resolution::P480.fit([481, 1000]), [480, 998]);
+        // the short side always lands on the target, so even a sliver keeps a pixel
+        assert_eq!(Resolution::P240.fit([241, 100_000_000]), [240, 99_585_062]);
+        assert_eq!(Resolution::P240.fit([100_000_000, 241]), [99_585_062, 240]);
+    }
+}
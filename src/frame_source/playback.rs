@@ -0,0 +1,514 @@
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Local};
+use web_time::{Duration, Instant};
+
+use crate::image::MyImage;
+
+use super::{yuv_to_rgb, Frame, FrameSource, Resolution};
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone)]
+#[serde(default)]
+pub struct PlaybackOptions {
+    /// a folder of png/jpeg files, or an `.mjpeg` / `.y4m` file
+    pub path: String,
+    pub looping: bool,
+    /// y4m files carry their own frame rate, this is used for everything else
+    pub fps: f32,
+}
+
+impl Default for PlaybackOptions {
+    fn default() -> Self {
+        PlaybackOptions {
+            path: String::new(),
+            looping: true,
+            fps: 10.0,
+        }
+    }
+}
+
+/// Plays back recorded footage from disk, either in real time or one frame at a time.
+pub struct PlaybackSource {
+    pub options: PlaybackOptions,
+    footage: Option<Footage>,
+    started: Instant,
+    held: Option<usize>,
+    /// the last decoded frame, so repaints don't decode the same frame again
+    cache: Option<(usize, Resolution, MyImage)>,
+}
+
+impl PlaybackSource {
+    pub fn new(options: PlaybackOptions) -> Self {
+        PlaybackSource {
+            options,
+            footage: None,
+            started: Instant::now(),
+            held: None,
+            cache: None,
+        }
+    }
+
+    fn fps(&self) -> f32 {
+        match &self.footage {
+            Some(Footage::Y4m { header, .. }) => header.fps,
+            _ => None,
+        }
+        .unwrap_or(self.options.fps)
+        .max(0.1)
+    }
+
+    fn current(&self) -> usize {
+        let len = self.footage.as_ref().map_or(1, Footage::len).max(1);
+        let index = self
+            .held
+            .unwrap_or((self.started.elapsed().as_secs_f32() * self.fps()) as usize);
+        if self.options.looping {
+            index % len
+        } else {
+            index.min(len - 1)
+        }
+    }
+}
+
+impl FrameSource for PlaybackSource {
+    fn name(&self) -> String {
+        let file = Path::new(&self.options.path)
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        format!("playback ({file})")
+    }
+
+    fn start(&mut self) -> anyhow::Result<()> {
+        self.cache = None;
+        self.footage = None;
+        let footage = Footage::open(Path::new(&self.options.path))?;
+        if footage.len() == 0 {
+            return Err(anyhow!("no frames found in {}", self.options.path));
+        }
+        self.footage = Some(footage);
+        self.started = Instant::now();
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.footage = None;
+        self.cache = None;
+    }
+
+    fn next_frame(&mut self, resolution: Resolution) -> anyhow::Result<Frame> {
+        let index = self.current();
+        let footage = self.footage.as_ref().ok_or(anyhow!("no footage loaded"))?;
+        let image = match &self.cache {
+            Some((i, res, image)) if *i == index && *res == resolution => image.clone(),
+            _ => {
+                let image = footage.decode(index)?;
+                let image = image.resized(resolution.fit([image.width, image.height]))?;
+                self.cache = Some((index, resolution, image.clone()));
+                image
+            }
+        };
+        let local: DateTime<Local> = Local::now();
+        Ok(Frame {
+            image: MyImage {
+                name: local.format("%Y-%m-%d %H-%M-%S").to_string(),
+                ..image
+            },
+            timestamp: Duration::from_secs_f32(index as f32 / self.fps()),
+        })
+    }
+
+    fn position(&self) -> Option<(usize, usize)> {
+        self.footage.as_ref().map(|f| (self.current(), f.len()))
+    }
+
+    fn hold(&mut self, frame: Option<usize>) {
+        let len = self.footage.as_ref().map_or(1, Footage::len).max(1);
+        match frame {
+            Some(frame) => self.held = Some(frame.min(len - 1)),
+            None => {
+                // carry on playing from wherever we were held
+                let current = self.current();
+                self.held = None;
+                let played = Duration::from_secs_f32(current as f32 / self.fps());
+                self.started = Instant::now()
+                    .checked_sub(played)
+                    .unwrap_or_else(Instant::now);
+            }
+        }
+    }
+}
+
+enum Footage {
+    Images(Vec<PathBuf>),
+    Mjpeg {
+        data: Vec<u8>,
+        frames: Vec<Range<usize>>,
+    },
+    Y4m {
+        data: Vec<u8>,
+        header: Y4mHeader,
+        frames: Vec<usize>,
+    },
+}
+
+impl Footage {
+    fn open(path: &Path) -> anyhow::Result<Footage> {
+        if path.is_dir() {
+            let mut files = std::fs::read_dir(path)?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| {
+                    p.extension().is_some_and(|ext| {
+                        ["png", "jpg", "jpeg"]
+                            .contains(&ext.to_string_lossy().to_lowercase().as_str())
+                    })
+                })
+                .collect::<Vec<PathBuf>>();
+            files.sort();
+            return Ok(Footage::Images(files));
+        }
+
+        let data = std::fs::read(path)?;
+        if data.starts_with(b"YUV4MPEG2 ") {
+            let (header, frames) = parse_y4m(&data)?;
+            Ok(Footage::Y4m {
+                data,
+                header,
+                frames,
+            })
+        } else if data.starts_with(&[0xFF, 0xD8]) {
+            let frames = split_mjpeg(&data);
+            Ok(Footage::Mjpeg { data, frames })
+        } else {
+            Err(anyhow!(
+                "{} is not a folder, mjpeg or y4m file",
+                path.display()
+            ))
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Footage::Images(files) => files.len(),
+            Footage::Mjpeg { frames, .. } => frames.len(),
+            Footage::Y4m { frames, .. } => frames.len(),
+        }
+    }
+
+    fn decode(&self, index: usize) -> anyhow::Result<MyImage> {
+        match self {
+            Footage::Images(files) => Ok(::image::open(&files[index])?.into()),
+            Footage::Mjpeg { data, frames } => Ok(::image::load_from_memory_with_format(
+                &data[frames[index].clone()],
+                ::image::ImageFormat::Jpeg,
+            )?
+            .into()),
+            Footage::Y4m {
+                data,
+                header,
+                frames,
+            } => Ok(header.to_rgba(&data[frames[index]..frames[index] + header.frame_size()])),
+        }
+    }
+}
+
+/// Finds each jpeg in a stream of concatenated jpegs (what most "mjpeg" files are).
+pub fn split_mjpeg(data: &[u8]) -> Vec<Range<usize>> {
+    let mut frames = vec![];
+    let mut start = 0;
+    // anything between one jpeg's EOI and the next SOI is skipped
+    while let Some(offset) = data[start..].windows(2).position(|w| w == [0xFF, 0xD8]) {
+        start += offset;
+        match jpeg_end(data, start) {
+            Some(end) => {
+                frames.push(start..end);
+                start = end;
+            }
+            // ignore a partly written last frame
+            None => break,
+        }
+    }
+    frames
+}
+
+/// Walks the marker segments of the jpeg at `start` and returns where its EOI marker ends.
+/// Segments are skipped whole, so a jpeg inside one (like an EXIF thumbnail) isn't split off.
+fn jpeg_end(data: &[u8], start: usize) -> Option<usize> {
+    let mut pos = start + 2;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        pos += 2;
+        match marker {
+            // a fill byte, the marker is in the next one
+            0xFF => pos -= 1,
+            0xD9 => return Some(pos),
+            // markers without a length
+            0x01 | 0xD0..=0xD7 => {}
+            _ => {
+                pos += u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]) as usize;
+                if marker == 0xDA {
+                    // the scan runs until a marker that isn't a stuffed zero or a restart
+                    pos += data.get(pos..)?.windows(2).position(|w| {
+                        w[0] == 0xFF && w[1] != 0 && !(0xD0..=0xD7).contains(&w[1])
+                    })?;
+                }
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Chroma {
+    C420,
+    C422,
+    C444,
+    Mono,
+}
+
+#[derive(Debug)]
+pub struct Y4mHeader {
+    pub width: usize,
+    pub height: usize,
+    pub fps: Option<f32>,
+    pub chroma: Chroma,
+}
+
+impl Y4mHeader {
+    pub fn parse(line: &str) -> anyhow::Result<Y4mHeader> {
+        let mut header = Y4mHeader {
+            width: 0,
+            height: 0,
+            fps: None,
+            chroma: Chroma::C420,
+        };
+        for param in line.split(' ').skip(1) {
+            let (tag, value) = param.split_at(param.chars().next().map_or(0, char::len_utf8));
+            match tag {
+                "W" => header.width = value.parse()?,
+                "H" => header.height = value.parse()?,
+                "F" => {
+                    if let Some((n, d)) = value.split_once(':') {
+                        let (n, d) = (n.parse::<f32>()?, d.parse::<f32>()?);
+                        if n > 0.0 && d > 0.0 {
+                            header.fps = Some(n / d);
+                        }
+                    }
+                }
+                "C" => {
+                    header.chroma = match value {
+                        "420" | "420jpeg" | "420paldv" | "420mpeg2" => Chroma::C420,
+                        "422" => Chroma::C422,
+                        "444" => Chroma::C444,
+                        "mono" => Chroma::Mono,
+                        _ => return Err(anyhow!("unsupported y4m colorspace C{value}")),
+                    }
+                }
+                _ => {}
+            }
+        }
+        if header.width == 0 || header.height == 0 {
+            return Err(anyhow!("y4m header is missing its size"));
+        }
+        Ok(header)
+    }
+
+    /// Width and height of each chroma plane.
+    fn chroma_size(&self) -> (usize, usize) {
+        match self.chroma {
+            Chroma::C420 => (self.width.div_ceil(2), self.height.div_ceil(2)),
+            Chroma::C422 => (self.width.div_ceil(2), self.height),
+            Chroma::C444 => (self.width, self.height),
+            Chroma::Mono => (0, 0),
+        }
+    }
+
+    pub fn frame_size(&self) -> usize {
+        let (cw, ch) = self.chroma_size();
+        self.width * self.height + 2 * cw * ch
+    }
+
+    /// Converts one frame of planar (limited range, bt.601) yuv into rgba.
+    pub fn to_rgba(&self, frame: &[u8]) -> MyImage {
+        let (cw, ch) = self.chroma_size();
+        let luma = &frame[..self.width * self.height];
+        let cb = &frame[self.width * self.height..][..cw * ch];
+        let cr = &frame[self.width * self.height + cw * ch..][..cw * ch];
+        let mut bytes = Vec::with_capacity(self.width * self.height * 4);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (u, v) = if self.chroma == Chroma::Mono {
+                    (128, 128)
+                } else {
+                    let i = x * cw / self.width + y * ch / self.height * cw;
+                    (cb[i], cr[i])
+                };
+                let [r, g, b] = yuv_to_rgb(luma[x + y * self.width], u, v);
+                bytes.extend_from_slice(&[r, g, b, 255]);
+            }
+        }
+        MyImage {
+            width: self.width as u32,
+            height: self.height as u32,
+            bytes,
+            ..Default::default()
+        }
+    }
+}
+
+/// Reads the stream header and returns the offset of every frame's pixel data.
+pub fn parse_y4m(data: &[u8]) -> anyhow::Result<(Y4mHeader, Vec<usize>)> {
+    let line_end = |from: usize| {
+        data[from..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map(|i| from + i)
+            .ok_or(anyhow!("truncated y4m file"))
+    };
+    let end = line_end(0)?;
+    let header = Y4mHeader::parse(std::str::from_utf8(&data[..end])?)?;
+
+    let mut frames = vec![];
+    let mut pos = end + 1;
+    while data[pos..].starts_with(b"FRAME") {
+        let start = line_end(pos)? + 1;
+        if start + header.frame_size() > data.len() {
+            // ignore a partly written last frame
+            break;
+        }
+        frames.push(start);
+        pos = start + header.frame_size();
+    }
+    Ok((header, frames))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = std::io::Cursor::new(vec![]);
+        ::image::RgbImage::from_pixel(width, height, ::image::Rgb([200, 100, 50]))
+            .write_to(&mut bytes, ::image::ImageFormat::Jpeg)
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    /// A jpeg carrying another (thumbnail) jpeg in an APP1 segment.
+    fn with_thumbnail(jpeg: &[u8], thumbnail: &[u8]) -> Vec<u8> {
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend_from_slice(thumbnail);
+        let mut data = jpeg[..2].to_vec();
+        data.extend_from_slice(&[0xFF, 0xE1]);
+        data.extend_from_slice(&(app1.len() as u16 + 2).to_be_bytes());
+        data.extend_from_slice(&app1);
+        data.extend_from_slice(&jpeg[2..]);
+        data
+    }
+
+    #[test]
+    fn split_concatenated_jpegs() {
+        let (a, b) = (jpeg(4, 2), jpeg(3, 5));
+        let data = [a.clone(), b.clone()].concat();
+        let frames = split_mjpeg(&data);
+        assert_eq!(frames, vec![0..a.len(), a.len()..data.len()]);
+        let second = ::image::load_from_memory(&data[frames[1].clone()]).unwrap();
+        assert_eq!((second.width(), second.height()), (3, 5));
+    }
+
+    #[test]
+    fn split_skips_exif_thumbnails() {
+        let frame = with_thumbnail(&jpeg(8, 8), &jpeg(2, 2));
+        let data = [frame.clone(), frame.clone()].concat();
+        assert_eq!(
+            split_mjpeg(&data),
+            vec![0..frame.len(), frame.len()..data.len()]
+        );
+    }
+
+    #[test]
+    fn split_skips_stuffed_bytes_and_restarts() {
+        // SOI, SOS with a 1 byte header, scan data with a stuffed FF and a restart, EOI
+        let frame = [
+            0xFF, 0xD8, 0xFF, 0xDA, 0x00, 0x03, 0x01, 0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56,
+            0xFF, 0xD9,
+        ];
+        let data = [&frame[..], &[0x00, 0x00], &frame[..]].concat();
+        assert_eq!(split_mjpeg(&data), vec![0..16, 18..34]);
+    }
+
+    #[test]
+    fn split_ignores_truncated_last_frame() {
+        let a = jpeg(4, 4);
+        let data = [&a[..], &a[..a.len() / 2]].concat();
+        assert_eq!(split_mjpeg(&data), vec![0..a.len()]);
+    }
+
+    fn y4m(header: &str, frames: &[&[u8]]) -> Vec<u8> {
+        let mut data = format!("{header}\n").into_bytes();
+        for frame in frames {
+            data.extend_from_slice(b"FRAME\n");
+            data.extend_from_slice(frame);
+        }
+        data
+    }
+
+    #[test]
+    fn parse_y4m_420() {
+        // 3x3 has 2x2 chroma planes: 9 + 2 * 4 bytes
+        let data = y4m("YUV4MPEG2 W3 H3 F30000:1001 Ip", &[&[0; 17], &[1; 17]]);
+        let (header, frames) = parse_y4m(&data).unwrap();
+        assert_eq!(
+            (header.width, header.height, header.chroma),
+            (3, 3, Chroma::C420)
+        );
+        assert!((header.fps.unwrap() - 29.97).abs() < 0.01);
+        assert_eq!(header.frame_size(), 17);
+        assert_eq!(frames, vec![37, 60]);
+    }
+
+    #[test]
+    fn parse_y4m_422() {
+        // 3x2 has 2x2 chroma planes: 6 + 2 * 4 bytes
+        let data = y4m("YUV4MPEG2 W3 H2 C422", &[&[0; 14]]);
+        let (header, frames) = parse_y4m(&data).unwrap();
+        assert_eq!(header.chroma, Chroma::C422);
+        assert_eq!(header.frame_size(), 14);
+        assert_eq!(frames.len(), 1);
+        let image = header.to_rgba(&data[frames[0]..][..header.frame_size()]);
+        assert_eq!(image.bytes.len(), 3 * 2 * 4);
+    }
+
+    #[test]
+    fn parse_y4m_mono() {
+        let data = y4m("YUV4MPEG2 W2 H1 Cmono", &[&[16, 235]]);
+        let (header, frames) = parse_y4m(&data).unwrap();
+        assert_eq!(header.frame_size(), 2);
+        let image = header.to_rgba(&data[frames[0]..][..header.frame_size()]);
+        // no chroma means grey, from limited range black to (nearly) white
+        assert_eq!(image.bytes[..4], [0, 0, 0, 255]);
+        assert!(image.bytes[4..7]
+            .iter()
+            .all(|&c| c == image.bytes[4] && c >= 254));
+    }
+
+    #[test]
+    fn parse_y4m_ignores_truncated_last_frame() {
+        let data = y4m("YUV4MPEG2 W2 H2 C444", &[&[0; 12], &[0; 12], &[0; 5]]);
+        let (_, frames) = parse_y4m(&data).unwrap();
+        assert_eq!(frames.len(), 2);
+    }
+
+    #[test]
+    fn parse_y4m_rejects_bad_headers() {
+        assert!(parse_y4m(b"YUV4MPEG2 W2 C420\n").is_err());
+        assert!(parse_y4m(b"YUV4MPEG2 W2 H2 C411\n").is_err());
+        assert!(parse_y4m(b"YUV4MPEG2 W2 H2").is_err());
+    }
+}
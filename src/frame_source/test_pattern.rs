@@ -0,0 +1,298 @@
+use std::f32::consts::PI;
+
+use chrono::{DateTime, Local};
+use strum_macros::EnumIter;
+use web_time::{Duration, Instant};
+
+use crate::image::MyImage;
+
+use super::{Frame, FrameSource, Resolution};
+
+#[derive(serde::Deserialize, serde::Serialize, EnumIter, PartialEq, Eq, Clone, Copy, Default)]
+pub enum TestPattern {
+    #[default]
+    ColorBars,
+    Gradient,
+    ZonePlate,
+    Checkerboard,
+}
+
+impl TestPattern {
+    pub fn name(&self) -> &str {
+        match self {
+            TestPattern::ColorBars => "color bars",
+            TestPattern::Gradient => "gradient",
+            TestPattern::ZonePlate => "zone plate",
+            TestPattern::Checkerboard => "checkerboard",
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone)]
+#[serde(default)]
+pub struct TestPatternOptions {
+    pub pattern: TestPattern,
+    pub width: u32,
+    pub height: u32,
+    pub fps: f32,
+    /// draw the frame counter and timestamp in the top left corner
+    pub burn_in: bool,
+}
+
+impl Default for TestPatternOptions {
+    fn default() -> Self {
+        TestPatternOptions {
+            pattern: TestPattern::ColorBars,
+            width: 640,
+            height: 480,
+            fps: 30.0,
+            burn_in: true,
+        }
+    }
+}
+
+/// Generates frames instead of capturing them, so everything after capture can be
+/// exercised without a camera. The image is a pure function of the frame index, the
+/// wall clock is only used to decide which frame is current.
+pub struct TestPatternSource {
+    pub options: TestPatternOptions,
+    started: Instant,
+}
+
+impl TestPatternSource {
+    pub fn new(options: TestPatternOptions) -> Self {
+        TestPatternSource {
+            options,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl FrameSource for TestPatternSource {
+    fn name(&self) -> String {
+        format!("test pattern ({})", self.options.pattern.name())
+    }
+
+    fn start(&mut self) -> anyhow::Result<()> {
+        self.started = Instant::now();
+        Ok(())
+    }
+
+    fn stop(&mut self) {}
+
+    fn next_frame(&mut self, resolution: Resolution) -> anyhow::Result<Frame> {
+        let fps = self.options.fps.max(0.1) as f64;
+        let index = (self.started.elapsed().as_secs_f64() * fps) as u64;
+        let [width, height] =
+            resolution.fit([self.options.width.max(1), self.options.height.max(1)]);
+        let local: DateTime<Local> = Local::now();
+        Ok(Frame {
+            image: MyImage {
+                name: local.format("%Y-%m-%d %H-%M-%S").to_string(),
+                ..render(&self.options, index, width, height)
+            },
+            timestamp: Duration::from_secs_f64(index as f64 / fps),
+        })
+    }
+}
+
+/// Draws frame `index` of the pattern at `width` x `height`.
+pub fn render(options: &TestPatternOptions, index: u64, width: u32, height: u32) -> MyImage {
+    let mut bytes = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let [r, g, b] = match options.pattern {
+                TestPattern::ColorBars => color_bars(x, y, width, height),
+                TestPattern::Gradient => gradient(x, y, width, height),
+                TestPattern::ZonePlate => zone_plate(x, y, width, height, index),
+                TestPattern::Checkerboard => checkerboard(x, y, height, index),
+            };
+            bytes.extend_from_slice(&[r, g, b, 255]);
+        }
+    }
+    let mut image = MyImage {
+        width,
+        height,
+        bytes,
+        ..Default::default()
+    };
+    if options.burn_in {
+        let seconds = index as f64 / options.fps.max(0.1) as f64;
+        let text = format!(
+            "{:06} {:02}:{:02}:{:05.2}",
+            index,
+            (seconds / 3600.0) as u32,
+            (seconds / 60.0) as u32 % 60,
+            seconds % 60.0
+        );
+        burn_in_text(&mut image, &text);
+    }
+    image
+}
+
+fn color_bars(x: u32, y: u32, width: u32, height: u32) -> [u8; 3] {
+    const GREY: [u8; 3] = [191, 191, 191];
+    const YELLOW: [u8; 3] = [191, 191, 0];
+    const CYAN: [u8; 3] = [0, 191, 191];
+    const GREEN: [u8; 3] = [0, 191, 0];
+    const MAGENTA: [u8; 3] = [191, 0, 191];
+    const RED: [u8; 3] = [191, 0, 0];
+    const BLUE: [u8; 3] = [0, 0, 191];
+    const BLACK: [u8; 3] = [19, 19, 19];
+
+    // everything is measured in 1/28ths of the width so the bottom row lines up
+    let column = x * 28 / width;
+    if y < height * 2 / 3 {
+        [GREY, YELLOW, CYAN, GREEN, MAGENTA, RED, BLUE][column as usize / 4]
+    } else if y < height * 3 / 4 {
+        [BLUE, BLACK, MAGENTA, BLACK, CYAN, BLACK, GREY][column as usize / 4]
+    } else {
+        match column {
+            0..=4 => [0, 33, 76],
+            5..=9 => [255, 255, 255],
+            10..=14 => [50, 0, 106],
+            15..=19 => BLACK,
+            // pluge: just below, at and just above black
+            20 => [9, 9, 9],
+            21 => BLACK,
+            22 => [29, 29, 29],
+            _ => BLACK,
+        }
+    }
+}
+
+fn gradient(x: u32, y: u32, width: u32, height: u32) -> [u8; 3] {
+    let v = (x * 255 / (width - 1).max(1)) as u8;
+    match y * 4 / height {
+        0 => [v, v, v],
+        1 => [v, 0, 0],
+        2 => [0, v, 0],
+        _ => [0, 0, v],
+    }
+}
+
+fn zone_plate(x: u32, y: u32, width: u32, height: u32, index: u64) -> [u8; 3] {
+    let dx = x as f32 - width as f32 / 2.0;
+    let dy = y as f32 - height as f32 / 2.0;
+    // reaches the nyquist frequency at the edge of the shorter side
+    let k = PI / (width.min(height) as f32).max(1.0);
+    let phase = index as f32 * 0.2;
+    let v = (0.5 + 0.5 * (k * (dx * dx + dy * dy) + phase).cos()) * 255.0;
+    [v as u8; 3]
+}
+
+fn checkerboard(x: u32, y: u32, height: u32, index: u64) -> [u8; 3] {
+    let size = (height / 8).max(1) as u64;
+    let offset = index * (size / 16).max(1);
+    if ((x as u64 + offset) / size + (y as u64 + offset) / size) % 2 == 0 {
+        [235, 235, 235]
+    } else {
+        [16, 16, 16]
+    }
+}
+
+/// 3x5 pixel glyphs, one bit per pixel, read left to right, top to bottom.
+fn glyph(c: char) -> u16 {
+    match c {
+        '0' => 0b111_101_101_101_111,
+        '1' => 0b010_110_010_010_111,
+        '2' => 0b111_001_111_100_111,
+        '3' => 0b111_001_111_001_111,
+        '4' => 0b101_101_111_001_001,
+        '5' => 0b111_100_111_001_111,
+        '6' => 0b111_100_111_101_111,
+        '7' => 0b111_001_010_010_010,
+        '8' => 0b111_101_111_101_111,
+        '9' => 0b111_101_111_001_111,
+        ':' => 0b000_010_000_010_000,
+        '.' => 0b000_000_000_000_010,
+        _ => 0,
+    }
+}
+
+/// Writes `text` in white on a black box in the top left corner of the image.
+fn burn_in_text(image: &mut MyImage, text: &str) {
+    let scale = (image.height / 60).max(1);
+    let char_width = 4 * scale;
+    let box_width = (text.chars().count() as u32 * char_width + scale).min(image.width);
+    let box_height = (7 * scale).min(image.height);
+
+    for y in 0..box_height {
+        for x in 0..box_width {
+            let gx = x.saturating_sub(scale);
+            let gy = y.saturating_sub(scale);
+            let on = x >= scale
+                && y >= scale
+                && gx % char_width < 3 * scale
+                && gy < 5 * scale
+                && text
+                    .chars()
+                    .nth((gx / char_width) as usize)
+                    .is_some_and(|c| {
+                        let bit = 14 - ((gy / scale) * 3 + (gx % char_width) / scale);
+                        glyph(c) >> bit & 1 == 1
+                    });
+            let v = if on { 255 } else { 0 };
+            let i = 4 * (x + y * image.width) as usize;
+            image.bytes[i..i + 3].copy_from_slice(&[v, v, v]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bars() -> TestPatternOptions {
+        TestPatternOptions {
+            pattern: TestPattern::ColorBars,
+            burn_in: false,
+            ..Default::default()
+        }
+    }
+
+    fn rgb(image: &MyImage, x: u32, y: u32) -> [u8; 3] {
+        let i = 4 * (x + y * image.width) as usize;
+        [image.bytes[i], image.bytes[i + 1], image.bytes[i + 2]]
+    }
+
+    #[test]
+    fn render_is_deterministic() {
+        for options in [
+            bars(),
+            TestPatternOptions {
+                pattern: TestPattern::ZonePlate,
+                ..Default::default()
+            },
+        ] {
+            let a = render(&options, 42, 64, 48);
+            let b = render(&options, 42, 64, 48);
+            assert_eq!(a.bytes, b.bytes);
+        }
+    }
+
+    #[test]
+    fn render_fills_every_pixel() {
+        let image = render(&Default::default(), 7, 33, 17);
+        assert_eq!((image.width, image.height), (33, 17));
+        assert_eq!(image.bytes.len(), 33 * 17 * 4);
+    }
+
+    #[test]
+    fn color_bars_have_the_expected_colors() {
+        // 56 wide makes every 1/28th of the width two pixels
+        let image = render(&bars(), 0, 56, 48);
+        assert_eq!(rgb(&image, 0, 0), [191, 191, 191]);
+        assert_eq!(rgb(&image, 8, 0), [191, 191, 0]);
+        assert_eq!(rgb(&image, 16, 10), [0, 191, 191]);
+        assert_eq!(rgb(&image, 55, 31), [0, 0, 191]);
+        // the short strip under the bars
+        assert_eq!(rgb(&image, 0, 33), [0, 0, 191]);
+        assert_eq!(rgb(&image, 8, 33), [19, 19, 19]);
+        // bottom row: -I, white, +Q and the pluge
+        assert_eq!(rgb(&image, 0, 47), [0, 33, 76]);
+        assert_eq!(rgb(&image, 10, 47), [255, 255, 255]);
+        assert_eq!(rgb(&image, 40, 47), [9, 9, 9]);
+        assert_eq!(rgb(&image, 44, 47), [29, 29, 29]);
+    }
+}
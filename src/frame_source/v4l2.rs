@@ -0,0 +1,820 @@
+use anyhow::anyhow;
+use chrono::{DateTime, Local};
+use strum_macros::EnumIter;
+use web_time::Instant;
+
+use crate::image::MyImage;
+
+use super::{yuv_to_rgb, Frame, FrameSource, Resolution};
+
+#[derive(
+    serde::Deserialize, serde::Serialize, EnumIter, PartialEq, Eq, Clone, Copy, Debug, Default,
+)]
+pub enum PixelFormat {
+    /// let the negotiation pick whichever format the camera can do at the requested size
+    #[default]
+    Auto,
+    Yuyv,
+    Mjpeg,
+}
+
+impl PixelFormat {
+    pub fn name(&self) -> &str {
+        match self {
+            PixelFormat::Auto => "auto",
+            PixelFormat::Yuyv => "YUYV",
+            PixelFormat::Mjpeg => "MJPEG",
+        }
+    }
+
+    pub fn fourcc(&self) -> u32 {
+        match self {
+            PixelFormat::Auto => 0,
+            PixelFormat::Yuyv => u32::from_le_bytes(*b"YUYV"),
+            PixelFormat::Mjpeg => u32::from_le_bytes(*b"MJPG"),
+        }
+    }
+
+    pub fn from_fourcc(fourcc: u32) -> Option<PixelFormat> {
+        match &fourcc.to_le_bytes() {
+            b"YUYV" => Some(PixelFormat::Yuyv),
+            b"MJPG" | b"JPEG" => Some(PixelFormat::Mjpeg),
+            _ => None,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone)]
+#[serde(default)]
+pub struct V4l2Options {
+    pub device: String,
+    pub format: PixelFormat,
+    pub size: [u32; 2],
+}
+
+impl Default for V4l2Options {
+    fn default() -> Self {
+        V4l2Options {
+            device: "/dev/video0".to_owned(),
+            format: PixelFormat::Auto,
+            size: [640, 480],
+        }
+    }
+}
+
+/// What a capture device says it can do.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceInfo {
+    pub path: String,
+    pub name: String,
+    pub formats: Vec<FormatInfo>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormatInfo {
+    pub format: PixelFormat,
+    pub sizes: Vec<[u32; 2]>,
+}
+
+/// The handful of operations the capture source needs from a V4L2 device.
+///
+/// [`LinuxDevice`] does this with real ioctls, anything else (like a fake device) only
+/// has to hand back frames in one of the supported formats.
+pub trait V4l2Device {
+    fn info(&mut self) -> anyhow::Result<DeviceInfo>;
+    /// Asks for a format, and returns the format and size the driver actually picked.
+    fn set_format(
+        &mut self,
+        format: PixelFormat,
+        size: [u32; 2],
+    ) -> anyhow::Result<(PixelFormat, [u32; 2])>;
+    fn stream_on(&mut self) -> anyhow::Result<()>;
+    fn stream_off(&mut self);
+    /// The next filled buffer, or `None` if there isn't one ready yet.
+    fn dequeue(&mut self) -> anyhow::Result<Option<Vec<u8>>>;
+}
+
+/// Picks the format and size to ask the device for, given what the user wants.
+pub fn negotiate(
+    info: &DeviceInfo,
+    format: PixelFormat,
+    size: [u32; 2],
+) -> anyhow::Result<(PixelFormat, [u32; 2])> {
+    let closest = |f: &FormatInfo| {
+        f.sizes
+            .iter()
+            .copied()
+            .min_by_key(|s| (s[0] as i64 * s[1] as i64 - size[0] as i64 * size[1] as i64).abs())
+    };
+    let usable = info.formats.iter().filter(|f| !f.sizes.is_empty());
+    let chosen = match format {
+        PixelFormat::Auto => usable
+            .clone()
+            // uncompressed is cheapest to convert, as long as it can do the size asked for
+            .find(|f| f.format == PixelFormat::Yuyv && f.sizes.contains(&size))
+            .or_else(|| usable.clone().find(|f| f.format == PixelFormat::Mjpeg))
+            .or_else(|| usable.clone().next()),
+        format => usable.clone().find(|f| f.format == format),
+    }
+    .ok_or(anyhow!("{} can't capture in {}", info.name, format.name()))?;
+    Ok((chosen.format, closest(chosen).unwrap_or(size)))
+}
+
+/// Turns one captured buffer into an image.
+pub fn decode(format: PixelFormat, size: [u32; 2], buffer: &[u8]) -> anyhow::Result<MyImage> {
+    match format {
+        PixelFormat::Yuyv => {
+            let [width, height] = size;
+            if buffer.len() < (width * height * 2) as usize {
+                return Err(anyhow!("short YUYV buffer ({} bytes)", buffer.len()));
+            }
+            let mut bytes = Vec::with_capacity((width * height * 4) as usize);
+            // each 4 bytes are two pixels sharing their chroma: Y0 U Y1 V
+            for c in buffer[..(width * height * 2) as usize].chunks_exact(4) {
+                let [r, g, b] = yuv_to_rgb(c[0], c[1], c[3]);
+                bytes.extend_from_slice(&[r, g, b, 255]);
+                let [r, g, b] = yuv_to_rgb(c[2], c[1], c[3]);
+                bytes.extend_from_slice(&[r, g, b, 255]);
+            }
+            Ok(MyImage {
+                width,
+                height,
+                bytes,
+                ..Default::default()
+            })
+        }
+        PixelFormat::Mjpeg => {
+            Ok(::image::load_from_memory_with_format(buffer, ::image::ImageFormat::Jpeg)?.into())
+        }
+        PixelFormat::Auto => Err(anyhow!("format was never negotiated")),
+    }
+}
+
+/// Captures from a V4L2 device, such as a webcam on a linux desktop.
+pub struct V4l2Source {
+    pub options: V4l2Options,
+    device: Option<Box<dyn V4l2Device>>,
+    negotiated: (PixelFormat, [u32; 2]),
+    started: Instant,
+    /// frames don't arrive as often as the ui repaints, so hold on to the latest one
+    last: Option<MyImage>,
+}
+
+impl V4l2Source {
+    pub fn new(options: V4l2Options) -> Self {
+        V4l2Source {
+            options,
+            device: None,
+            negotiated: (PixelFormat::Auto, [0, 0]),
+            started: Instant::now(),
+            last: None,
+        }
+    }
+
+    /// Starts capturing from an already opened device.
+    pub fn start_with(&mut self, mut device: Box<dyn V4l2Device>) -> anyhow::Result<()> {
+        self.stop();
+        let info = device.info()?;
+        let (format, size) = negotiate(&info, self.options.format, self.options.size)?;
+        self.negotiated = device.set_format(format, size)?;
+        device.stream_on()?;
+        self.device = Some(device);
+        self.started = Instant::now();
+        Ok(())
+    }
+}
+
+impl FrameSource for V4l2Source {
+    fn name(&self) -> String {
+        format!("v4l2 ({})", self.options.device)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn start(&mut self) -> anyhow::Result<()> {
+        self.start_with(Box::new(LinuxDevice::open(&self.options.device)?))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn start(&mut self) -> anyhow::Result<()> {
+        Err(anyhow!("v4l2 is only available on linux"))
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut device) = self.device.take() {
+            device.stream_off();
+        }
+        self.last = None;
+    }
+
+    fn next_frame(&mut self, resolution: Resolution) -> anyhow::Result<Frame> {
+        let device = self.device.as_mut().ok_or(anyhow!("device not started"))?;
+        // drain the queue so we are always showing the newest frame
+        let mut newest = None;
+        while let Some(buffer) = device.dequeue()? {
+            newest = Some(buffer);
+        }
+        if let Some(buffer) = newest {
+            self.last = Some(decode(self.negotiated.0, self.negotiated.1, &buffer)?);
+        }
+        let last = self
+            .last
+            .as_ref()
+            .ok_or(anyhow!("waiting for the first frame"))?;
+        let local: DateTime<Local> = Local::now();
+        Ok(Frame {
+            image: MyImage {
+                name: local.format("%Y-%m-%d %H-%M-%S").to_string(),
+                ..last.resized(resolution.fit([last.width, last.height]))?
+            },
+            timestamp: self.started.elapsed(),
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::{list_devices, LinuxDevice};
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        ffi::c_void,
+        fs::{File, OpenOptions},
+        io,
+        os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    };
+
+    use anyhow::anyhow;
+
+    use super::{DeviceInfo, FormatInfo, PixelFormat, V4l2Device};
+
+    const BUF_TYPE_VIDEO_CAPTURE: u32 = 1;
+    const MEMORY_MMAP: u32 = 1;
+    const FIELD_NONE: u32 = 1;
+    const CAP_VIDEO_CAPTURE: u32 = 0x0000_0001;
+    const CAP_DEVICE_CAPS: u32 = 0x8000_0000;
+    const FRMSIZE_TYPE_DISCRETE: u32 = 1;
+    const BUFFER_COUNT: u32 = 4;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Capability {
+        driver: [u8; 16],
+        card: [u8; 32],
+        bus_info: [u8; 32],
+        version: u32,
+        capabilities: u32,
+        device_caps: u32,
+        reserved: [u32; 3],
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct FmtDesc {
+        index: u32,
+        type_: u32,
+        flags: u32,
+        description: [u8; 32],
+        pixelformat: u32,
+        mbus_code: u32,
+        reserved: [u32; 3],
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct FrmSizeEnum {
+        index: u32,
+        pixel_format: u32,
+        type_: u32,
+        /// either `width, height` or `min_width, max_width, step_width, min_height, max_height, step_height`
+        size: [u32; 6],
+        reserved: [u32; 2],
+    }
+
+    #[repr(C)]
+    #[derive(Default, Clone, Copy)]
+    struct PixFormat {
+        width: u32,
+        height: u32,
+        pixelformat: u32,
+        field: u32,
+        bytesperline: u32,
+        sizeimage: u32,
+        colorspace: u32,
+        priv_: u32,
+        flags: u32,
+        ycbcr_enc: u32,
+        quantization: u32,
+        xfer_func: u32,
+    }
+
+    #[repr(C)]
+    union FormatUnion {
+        pix: PixFormat,
+        raw_data: [u8; 200],
+        // the real union holds pointers, which makes it 8 byte aligned
+        _align: [u64; 25],
+    }
+
+    #[repr(C)]
+    struct Format {
+        type_: u32,
+        fmt: FormatUnion,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct RequestBuffers {
+        count: u32,
+        type_: u32,
+        memory: u32,
+        capabilities: u32,
+        flags: u8,
+        reserved: [u8; 3],
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Timecode {
+        type_: u32,
+        flags: u32,
+        frames: u8,
+        seconds: u8,
+        minutes: u8,
+        hours: u8,
+        userbits: [u8; 4],
+    }
+
+    #[repr(C)]
+    struct Buffer {
+        index: u32,
+        type_: u32,
+        bytesused: u32,
+        flags: u32,
+        field: u32,
+        timestamp: libc::timeval,
+        timecode: Timecode,
+        sequence: u32,
+        memory: u32,
+        /// `offset` for mmap buffers, but as wide as the pointer it shares a union with
+        m: u64,
+        length: u32,
+        reserved2: u32,
+        request_fd: u32,
+    }
+
+    impl Buffer {
+        fn new(index: u32) -> Buffer {
+            Buffer {
+                index,
+                type_: BUF_TYPE_VIDEO_CAPTURE,
+                bytesused: 0,
+                flags: 0,
+                field: 0,
+                timestamp: libc::timeval {
+                    tv_sec: 0,
+                    tv_usec: 0,
+                },
+                timecode: Timecode::default(),
+                sequence: 0,
+                memory: MEMORY_MMAP,
+                m: 0,
+                length: 0,
+                reserved2: 0,
+                request_fd: 0,
+            }
+        }
+    }
+
+    // the ioctl numbers encode these sizes, so they have to match the kernel's exactly
+    #[cfg(target_pointer_width = "64")]
+    const _: () = {
+        assert!(std::mem::size_of::<Capability>() == 104);
+        assert!(std::mem::size_of::<FmtDesc>() == 64);
+        assert!(std::mem::size_of::<FrmSizeEnum>() == 44);
+        assert!(std::mem::size_of::<Format>() == 208);
+        assert!(std::mem::size_of::<RequestBuffers>() == 20);
+        assert!(std::mem::size_of::<Buffer>() == 88);
+    };
+
+    const fn ioc(dir: u64, nr: u64, size: usize) -> u64 {
+        dir << 30 | (size as u64) << 16 | (b'V' as u64) << 8 | nr
+    }
+    const READ: u64 = 2;
+    const WRITE: u64 = 1;
+    const QUERYCAP: u64 = ioc(READ, 0, std::mem::size_of::<Capability>());
+    const ENUM_FMT: u64 = ioc(READ | WRITE, 2, std::mem::size_of::<FmtDesc>());
+    const S_FMT: u64 = ioc(READ | WRITE, 5, std::mem::size_of::<Format>());
+    const REQBUFS: u64 = ioc(READ | WRITE, 8, std::mem::size_of::<RequestBuffers>());
+    const QUERYBUF: u64 = ioc(READ | WRITE, 9, std::mem::size_of::<Buffer>());
+    const QBUF: u64 = ioc(READ | WRITE, 15, std::mem::size_of::<Buffer>());
+    const DQBUF: u64 = ioc(READ | WRITE, 17, std::mem::size_of::<Buffer>());
+    const STREAMON: u64 = ioc(WRITE, 18, std::mem::size_of::<i32>());
+    const STREAMOFF: u64 = ioc(WRITE, 19, std::mem::size_of::<i32>());
+    const ENUM_FRAMESIZES: u64 = ioc(READ | WRITE, 74, std::mem::size_of::<FrmSizeEnum>());
+
+    fn xioctl<T>(file: &File, request: u64, arg: &mut T) -> io::Result<()> {
+        loop {
+            // SAFETY: every request above is paired with the struct it was sized from
+            let r = unsafe {
+                libc::ioctl(file.as_raw_fd(), request as _, arg as *mut T as *mut c_void)
+            };
+            if r != -1 {
+                return Ok(());
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+
+    fn c_str(bytes: &[u8]) -> String {
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).to_string()
+    }
+
+    /// Common sizes that fit a stepwise or continuous frame size range.
+    fn stepwise_sizes(s: &[u32; 6]) -> Vec<[u32; 2]> {
+        let [min_w, max_w, step_w, min_h, max_h, step_h] = *s;
+        let mut sizes = [
+            [320, 240],
+            [640, 480],
+            [1280, 720],
+            [1920, 1080],
+            [3840, 2160],
+        ]
+        .into_iter()
+        .filter(|&[w, h]| {
+            (min_w..=max_w).contains(&w)
+                && (min_h..=max_h).contains(&h)
+                && (w - min_w) % step_w.max(1) == 0
+                && (h - min_h) % step_h.max(1) == 0
+        })
+        .collect::<Vec<_>>();
+        if !sizes.contains(&[max_w, max_h]) {
+            sizes.push([max_w, max_h]);
+        }
+        sizes
+    }
+
+    pub struct LinuxDevice {
+        path: String,
+        file: File,
+        buffers: Vec<(*mut c_void, usize)>,
+    }
+
+    impl LinuxDevice {
+        pub fn open(path: &str) -> anyhow::Result<LinuxDevice> {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(path)
+                .map_err(|e| anyhow!("failed to open {path}: {e}"))?;
+            Ok(LinuxDevice {
+                path: path.to_owned(),
+                file,
+                buffers: vec![],
+            })
+        }
+
+        fn unmap(&mut self) {
+            for (ptr, len) in self.buffers.drain(..) {
+                // SAFETY: these came from mmap with exactly this length
+                unsafe { libc::munmap(ptr, len) };
+            }
+        }
+    }
+
+    impl Drop for LinuxDevice {
+        fn drop(&mut self) {
+            self.stream_off();
+        }
+    }
+
+    impl V4l2Device for LinuxDevice {
+        fn info(&mut self) -> anyhow::Result<DeviceInfo> {
+            let mut cap = Capability::default();
+            xioctl(&self.file, QUERYCAP, &mut cap)?;
+            let caps = if cap.capabilities & CAP_DEVICE_CAPS != 0 {
+                cap.device_caps
+            } else {
+                cap.capabilities
+            };
+            if caps & CAP_VIDEO_CAPTURE == 0 {
+                return Err(anyhow!("{} is not a capture device", self.path));
+            }
+
+            let mut formats = vec![];
+            for index in 0.. {
+                let mut desc = FmtDesc {
+                    index,
+                    type_: BUF_TYPE_VIDEO_CAPTURE,
+                    ..Default::default()
+                };
+                if xioctl(&self.file, ENUM_FMT, &mut desc).is_err() {
+                    break;
+                }
+                let Some(format) = PixelFormat::from_fourcc(desc.pixelformat) else {
+                    continue;
+                };
+                let mut sizes = vec![];
+                for index in 0.. {
+                    let mut size = FrmSizeEnum {
+                        index,
+                        pixel_format: desc.pixelformat,
+                        ..Default::default()
+                    };
+                    if xioctl(&self.file, ENUM_FRAMESIZES, &mut size).is_err() {
+                        break;
+                    }
+                    if size.type_ == FRMSIZE_TYPE_DISCRETE {
+                        sizes.push([size.size[0], size.size[1]]);
+                    } else {
+                        sizes.extend(stepwise_sizes(&size.size));
+                        break;
+                    }
+                }
+                formats.push(FormatInfo { format, sizes });
+            }
+
+            Ok(DeviceInfo {
+                path: self.path.clone(),
+                name: c_str(&cap.card),
+                formats,
+            })
+        }
+
+        fn set_format(
+            &mut self,
+            format: PixelFormat,
+            size: [u32; 2],
+        ) -> anyhow::Result<(PixelFormat, [u32; 2])> {
+            let mut fmt = Format {
+                type_: BUF_TYPE_VIDEO_CAPTURE,
+                fmt: FormatUnion {
+                    pix: PixFormat {
+                        width: size[0],
+                        height: size[1],
+                        pixelformat: format.fourcc(),
+                        field: FIELD_NONE,
+                        ..Default::default()
+                    },
+                },
+            };
+            xioctl(&self.file, S_FMT, &mut fmt)?;
+            // SAFETY: the driver fills in `pix` for capture buffers
+            let pix = unsafe { fmt.fmt.pix };
+            let format = PixelFormat::from_fourcc(pix.pixelformat)
+                .ok_or(anyhow!("driver switched to an unsupported format"))?;
+            Ok((format, [pix.width, pix.height]))
+        }
+
+        fn stream_on(&mut self) -> anyhow::Result<()> {
+            let mut req = RequestBuffers {
+                count: BUFFER_COUNT,
+                type_: BUF_TYPE_VIDEO_CAPTURE,
+                memory: MEMORY_MMAP,
+                ..Default::default()
+            };
+            xioctl(&self.file, REQBUFS, &mut req)?;
+            for index in 0..req.count {
+                let mut buf = Buffer::new(index);
+                xioctl(&self.file, QUERYBUF, &mut buf)?;
+                // SAFETY: mapping the buffer exactly as the driver described it
+                let ptr = unsafe {
+                    libc::mmap(
+                        std::ptr::null_mut(),
+                        buf.length as usize,
+                        libc::PROT_READ | libc::PROT_WRITE,
+                        libc::MAP_SHARED,
+                        self.file.as_raw_fd(),
+                        buf.m as libc::off_t,
+                    )
+                };
+                if ptr == libc::MAP_FAILED {
+                    self.unmap();
+                    return Err(io::Error::last_os_error().into());
+                }
+                self.buffers.push((ptr, buf.length as usize));
+                xioctl(&self.file, QBUF, &mut buf)?;
+            }
+            let mut type_ = BUF_TYPE_VIDEO_CAPTURE as i32;
+            xioctl(&self.file, STREAMON, &mut type_)?;
+            Ok(())
+        }
+
+        fn stream_off(&mut self) {
+            if self.buffers.is_empty() {
+                return;
+            }
+            let mut type_ = BUF_TYPE_VIDEO_CAPTURE as i32;
+            xioctl(&self.file, STREAMOFF, &mut type_).ok();
+            self.unmap();
+            // give the buffers back to the driver
+            let mut req = RequestBuffers {
+                count: 0,
+                type_: BUF_TYPE_VIDEO_CAPTURE,
+                memory: MEMORY_MMAP,
+                ..Default::default()
+            };
+            xioctl(&self.file, REQBUFS, &mut req).ok();
+        }
+
+        fn dequeue(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+            let mut buf = Buffer::new(0);
+            if let Err(e) = xioctl(&self.file, DQBUF, &mut buf) {
+                return match e.kind() {
+                    io::ErrorKind::WouldBlock => Ok(None),
+                    _ => Err(e.into()),
+                };
+            }
+            let (ptr, len) = *self
+                .buffers
+                .get(buf.index as usize)
+                .ok_or(anyhow!("driver returned unknown buffer {}", buf.index))?;
+            // SAFETY: the buffer is mapped and the driver is done with it until we queue it again
+            let data = unsafe {
+                std::slice::from_raw_parts(ptr as *const u8, (buf.bytesused as usize).min(len))
+            }
+            .to_vec();
+            xioctl(&self.file, QBUF, &mut buf)?;
+            Ok(Some(data))
+        }
+    }
+
+    /// Every `/dev/video*` that can capture frames in a format we understand.
+    pub fn list_devices() -> Vec<DeviceInfo> {
+        let mut paths = std::fs::read_dir("/dev")
+            .map(|dir| {
+                dir.filter_map(|e| e.ok())
+                    .map(|e| e.path().to_string_lossy().to_string())
+                    .filter(|p| p.starts_with("/dev/video"))
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default();
+        paths.sort();
+        paths
+            .iter()
+            .filter_map(|p| LinuxDevice::open(p).ok()?.info().ok())
+            .filter(|info| !info.formats.is_empty())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    use super::*;
+
+    /// Hands out whatever buffers the test queued up.
+    struct FakeDevice {
+        info: DeviceInfo,
+        queue: Rc<RefCell<VecDeque<Vec<u8>>>>,
+    }
+
+    impl V4l2Device for FakeDevice {
+        fn info(&mut self) -> anyhow::Result<DeviceInfo> {
+            Ok(self.info.clone())
+        }
+        fn set_format(
+            &mut self,
+            format: PixelFormat,
+            size: [u32; 2],
+        ) -> anyhow::Result<(PixelFormat, [u32; 2])> {
+            Ok((format, size))
+        }
+        fn stream_on(&mut self) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn stream_off(&mut self) {}
+        fn dequeue(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+            Ok(self.queue.borrow_mut().pop_front())
+        }
+    }
+
+    fn device(formats: &[(PixelFormat, &[[u32; 2]])]) -> DeviceInfo {
+        DeviceInfo {
+            path: "/dev/video9".to_owned(),
+            name: "fake camera".to_owned(),
+            formats: formats
+                .iter()
+                .map(|(format, sizes)| FormatInfo {
+                    format: *format,
+                    sizes: sizes.to_vec(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Two pixels sharing neutral chroma, limited range black then white.
+    const BLACK_WHITE: [u8; 4] = [16, 128, 235, 128];
+
+    #[test]
+    fn negotiate_auto() {
+        let both = device(&[
+            (PixelFormat::Mjpeg, &[[640, 480], [1920, 1080]]),
+            (PixelFormat::Yuyv, &[[640, 480], [1280, 720]]),
+        ]);
+        // uncompressed when it has the exact size
+        assert_eq!(
+            negotiate(&both, PixelFormat::Auto, [640, 480]).unwrap(),
+            (PixelFormat::Yuyv, [640, 480])
+        );
+        // otherwise mjpeg at its closest size
+        assert_eq!(
+            negotiate(&both, PixelFormat::Auto, [1920, 1080]).unwrap(),
+            (PixelFormat::Mjpeg, [1920, 1080])
+        );
+        assert_eq!(
+            negotiate(&both, PixelFormat::Auto, [2000, 1100]).unwrap(),
+            (PixelFormat::Mjpeg, [1920, 1080])
+        );
+    }
+
+    #[test]
+    fn negotiate_yuyv_only() {
+        let yuyv = device(&[(PixelFormat::Yuyv, &[[320, 240], [640, 480]])]);
+        assert_eq!(
+            negotiate(&yuyv, PixelFormat::Auto, [1280, 720]).unwrap(),
+            (PixelFormat::Yuyv, [640, 480])
+        );
+        assert_eq!(
+            negotiate(&yuyv, PixelFormat::Yuyv, [300, 200]).unwrap(),
+            (PixelFormat::Yuyv, [320, 240])
+        );
+        assert!(negotiate(&yuyv, PixelFormat::Mjpeg, [640, 480]).is_err());
+    }
+
+    #[test]
+    fn negotiate_mjpeg_only() {
+        let mjpeg = device(&[(PixelFormat::Mjpeg, &[[1280, 720]])]);
+        assert_eq!(
+            negotiate(&mjpeg, PixelFormat::Auto, [640, 480]).unwrap(),
+            (PixelFormat::Mjpeg, [1280, 720])
+        );
+        assert_eq!(
+            negotiate(&mjpeg, PixelFormat::Mjpeg, [640, 480]).unwrap(),
+            (PixelFormat::Mjpeg, [1280, 720])
+        );
+        assert!(negotiate(&mjpeg, PixelFormat::Yuyv, [640, 480]).is_err());
+    }
+
+    #[test]
+    fn negotiate_no_match() {
+        assert!(negotiate(&device(&[]), PixelFormat::Auto, [640, 480]).is_err());
+        // a format without any sizes is no use either
+        let sizeless = device(&[(PixelFormat::Yuyv, &[])]);
+        assert!(negotiate(&sizeless, PixelFormat::Auto, [640, 480]).is_err());
+        assert!(negotiate(&sizeless, PixelFormat::Yuyv, [640, 480]).is_err());
+    }
+
+    #[test]
+    fn decode_yuyv() {
+        let image = decode(PixelFormat::Yuyv, [2, 1], &BLACK_WHITE).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.bytes, vec![0, 0, 0, 255, 254, 254, 254, 255]);
+    }
+
+    #[test]
+    fn decode_short_buffer() {
+        assert!(decode(PixelFormat::Yuyv, [2, 1], &BLACK_WHITE[..3]).is_err());
+        assert!(decode(PixelFormat::Yuyv, [2, 2], &BLACK_WHITE).is_err());
+        assert!(decode(PixelFormat::Auto, [2, 1], &BLACK_WHITE).is_err());
+    }
+
+    #[test]
+    fn next_frame_is_the_newest() {
+        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        let mut source = V4l2Source::new(V4l2Options {
+            format: PixelFormat::Yuyv,
+            size: [2, 1],
+            ..Default::default()
+        });
+        assert!(source.next_frame(Resolution::Full).is_err());
+        source
+            .start_with(Box::new(FakeDevice {
+                info: device(&[(PixelFormat::Yuyv, &[[2, 1]])]),
+                queue: queue.clone(),
+            }))
+            .unwrap();
+        // nothing has arrived yet
+        assert!(source.next_frame(Resolution::Full).is_err());
+
+        let [y0, u, y1, v] = BLACK_WHITE;
+        queue
+            .borrow_mut()
+            .extend([vec![y0, u, y0, v], vec![y1, u, y0, v], vec![y1, u, y1, v]]);
+        let frame = source.next_frame(Resolution::Full).unwrap();
+        assert!(queue.borrow().is_empty());
+        assert_eq!(
+            frame.image.bytes,
+            vec![254, 254, 254, 255, 254, 254, 254, 255]
+        );
+
+        // keeps showing the last frame until another arrives
+        let again = source.next_frame(Resolution::Full).unwrap();
+        assert_eq!(again.image.bytes, frame.image.bytes);
+    }
+}
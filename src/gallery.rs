@@ -0,0 +1,151 @@
+use egui::{pos2, vec2, Color32, Rect, RichText, Sense, TextureHandle, Ui};
+use egui_phosphor::regular::WARNING;
+
+use crate::MyApp;
+
+/// How many pixels thumbnails get on their longer side.
+const THUMBNAIL_SIZE: u32 = 128;
+/// How big thumbnails are drawn, in points.
+const CELL_SIZE: f32 = 48.0;
+/// Thumbnails made each frame, so opening the save tab with lots of photos doesn't stall.
+const THUMBNAILS_PER_FRAME: usize = 2;
+
+impl MyApp {
+    /// A small copy of `photos[index]` with its recipe applied, made once the photo has
+    /// loaded and remade when the recipe changes. `None` while the photo is still loading.
+    fn thumbnail(
+        &mut self,
+        ctx: &egui::Context,
+        index: usize,
+        budget: &mut usize,
+    ) -> anyhow::Result<Option<TextureHandle>> {
+        let photo = &self.photos[index];
+        let cached = self.thumbnails.get(&photo.id).cloned();
+        match cached {
+            Some((recipe, texture)) if recipe == photo.recipe || *budget == 0 => {
+                return Ok(Some(texture))
+            }
+            None if *budget == 0 => return Ok(None),
+            _ => {}
+        }
+
+        let was_loaded = photo.is_loaded();
+        if !self.load_photo(index)? {
+            return Ok(cached.map(|(_, texture)| texture));
+        }
+        *budget -= 1;
+        let photo = &mut self.photos[index];
+        let scale = (THUMBNAIL_SIZE as f64 / photo.width.max(photo.height) as f64).min(1.0);
+        let size =
+            [photo.width, photo.height].map(|side| ((side as f64 * scale).round() as u32).max(1));
+        let mut small = photo.resized(size)?;
+        // only loaded for the thumbnail, no need to keep the full size pixels around
+        if !was_loaded {
+            photo.bytes = vec![];
+        }
+
+        let recipe = std::mem::take(&mut small.recipe);
+        self.process_image(&mut small, &recipe);
+        let texture = ctx.load_texture(
+            format!("thumbnail {}", small.id),
+            egui::ColorImage::from_rgba_premultiplied(
+                [small.width as usize, small.height as usize],
+                &small.bytes,
+            ),
+            egui::TextureOptions::LINEAR,
+        );
+        self.thumbnails.insert(small.id, (recipe, texture.clone()));
+        Ok(Some(texture))
+    }
+
+    /// Every photo as a grid of thumbnails. Clicking one opens it, ctrl+click adds it to (or
+    /// takes it out of) the photos to save and shift+click selects everything in between.
+    pub fn render_gallery(&mut self, ui: &mut Ui) {
+        let mut budget = THUMBNAILS_PER_FRAME;
+        let current = self.save_options.image_index.max(0) as usize;
+        // where shift+click selects from
+        let anchor_id = ui.make_persistent_id("gallery anchor");
+
+        let rows = 3.0;
+        egui::ScrollArea::vertical()
+            .id_salt("gallery")
+            .max_height(rows * (CELL_SIZE + ui.spacing().item_spacing.y))
+            .show(ui, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    for i in 0..self.photos.len() {
+                        let (rect, response) =
+                            ui.allocate_exact_size(vec2(CELL_SIZE, CELL_SIZE), Sense::click());
+                        let response = response.on_hover_text(&self.photos[i].name);
+                        if response.clicked() {
+                            let modifiers = ui.input(|input| input.modifiers);
+                            let anchor = ui
+                                .data(|d| d.get_temp::<usize>(anchor_id))
+                                .filter(|&a| a < self.photos.len());
+                            match anchor {
+                                Some(anchor) if modifiers.shift => {
+                                    for photo in &mut self.photos[anchor.min(i)..=anchor.max(i)] {
+                                        photo.save = true;
+                                    }
+                                }
+                                _ if modifiers.command => {
+                                    self.photos[i].save = !self.photos[i].save;
+                                    ui.data_mut(|d| d.insert_temp(anchor_id, i));
+                                }
+                                _ => {
+                                    self.save_options.image_index = i as i32;
+                                    ui.data_mut(|d| d.insert_temp(anchor_id, i));
+                                }
+                            }
+                        }
+
+                        // only make thumbnails that are actually on screen
+                        if !ui.is_rect_visible(rect) {
+                            continue;
+                        }
+                        let painter = ui.painter_at(rect);
+                        if self.photos[i].save {
+                            painter.rect_filled(rect, 2.0, ui.visuals().selection.bg_fill);
+                        }
+                        match self.thumbnail(ui.ctx(), i, &mut budget) {
+                            Ok(Some(texture)) => {
+                                let size = texture.size_vec2()
+                                    * ((CELL_SIZE - 4.0) / texture.size_vec2().max_elem());
+                                painter.image(
+                                    texture.id(),
+                                    Rect::from_center_size(rect.center(), size),
+                                    Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)),
+                                    Color32::WHITE,
+                                );
+                            }
+                            Ok(None) => {
+                                egui::Spinner::new().paint_at(ui, rect.shrink(CELL_SIZE / 4.0));
+                                ui.ctx().request_repaint();
+                            }
+                            // a photo missing from the store, there's nothing to show
+                            Err(_) => {
+                                painter.text(
+                                    rect.center(),
+                                    egui::Align2::CENTER_CENTER,
+                                    WARNING,
+                                    egui::FontId::proportional(CELL_SIZE / 3.0),
+                                    ui.visuals().warn_fg_color,
+                                );
+                            }
+                        }
+                        if i == current {
+                            painter.rect_stroke(
+                                rect.shrink(1.0),
+                                2.0,
+                                ui.visuals().selection.stroke,
+                            );
+                        }
+                    }
+                });
+            });
+        ui.label(
+            RichText::new("click to open, ctrl+click to select, shift+click to select a range")
+                .small()
+                .weak(),
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use eframe::{
+    egui_glow::ShaderVersion,
+    glow::{self, Context, Framebuffer, HasContext, Program, Texture, VertexArray},
+};
+
+use crate::image::MyImage;
+
+const VERTEX_SHADER_SOURCE: &str = r#"
+    const vec2 coords[3] = vec2[3] (
+        vec2(-1.0, -1.0),
+        vec2(3.0, -1.0),
+        vec2(-1.0, 3.0)
+    );
+    out vec2 v_uv;
+    void main()
+    {
+        gl_Position = vec4(coords[gl_VertexID], 0., 1.);
+        v_uv = coords[gl_VertexID] * 0.5 + 0.5;
+    }
+"#;
+
+/// Everything a pass's fragment shader can use, on top of its own uniforms.
+const FRAGMENT_SHADER_PREFIX: &str = r#"
+    uniform sampler2D u_image;
+    uniform vec2 u_resolution;
+    in vec2 v_uv;
+    out vec4 color;
+"#;
+
+/// One step of image processing. It runs as a fragment shader when there is a gl context,
+/// and on the cpu when there isn't (or the shader fails), so both have to do the same thing.
+pub trait Pass {
+    /// The rest of the fragment shader (uniforms and `main`), it should read `u_image` at
+    /// `v_uv` and write `color`.
+    fn fragment(&self) -> String;
+    /// Values for the `float` uniforms declared in [`Pass::fragment`].
+    fn uniforms(&self) -> Vec<(&'static str, f32)>;
+    fn apply_cpu(&self, img: &mut MyImage);
+}
+
+/// Runs [`Pass`]es over an image on the gpu, ping-ponging between two framebuffers.
+///
+/// Programs are compiled once per distinct shader and kept around, as are the textures
+/// while the image size stays the same.
+#[derive(Default)]
+pub struct GlPipeline {
+    programs: HashMap<String, Program>,
+    vertex_array: Option<VertexArray>,
+    targets: Vec<(Texture, Framebuffer)>,
+    size: [u32; 2],
+}
+
+impl GlPipeline {
+    pub fn run(
+        &mut self,
+        gl: &Context,
+        passes: &[&dyn Pass],
+        img: &mut MyImage,
+    ) -> anyhow::Result<()> {
+        if passes.is_empty() || img.width == 0 || img.height == 0 {
+            return Ok(());
+        }
+        if img.bytes.len() != MyImage::rgba_len(img.width, img.height) {
+            return Err(anyhow!("image doesn't match its size"));
+        }
+        let version = ShaderVersion::get(gl);
+        if !version.is_new_shader_interface() {
+            return Err(anyhow!("{version:?} is too old for the processing shaders"));
+        }
+        // compile everything up front so a broken shader doesn't leave the gl state half set up
+        let programs = passes
+            .iter()
+            .map(|p| self.program(gl, version, &p.fragment()))
+            .collect::<anyhow::Result<Vec<Program>>>()?;
+
+        // SAFETY: only objects created on this context are used. The scissor test, blending,
+        // sRGB framebuffer and viewport are left changed, egui_glow sets all of them again at
+        // the start of its next paint, and the bindings are reset to the defaults at the end
+        unsafe {
+            self.prepare_targets(gl, [img.width, img.height])?;
+            let (width, height) = (img.width as i32, img.height as i32);
+
+            gl.active_texture(glow::TEXTURE0);
+            gl.bind_texture(glow::TEXTURE_2D, Some(self.targets[0].0));
+            gl.tex_sub_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                0,
+                0,
+                width,
+                height,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                glow::PixelUnpackData::Slice(&img.bytes),
+            );
+
+            // egui leaves these on after painting the last frame
+            gl.disable(glow::SCISSOR_TEST);
+            gl.disable(glow::BLEND);
+            if !version.is_embedded() {
+                gl.disable(glow::FRAMEBUFFER_SRGB);
+            }
+            gl.viewport(0, 0, width, height);
+            gl.bind_vertex_array(self.vertex_array);
+
+            let mut source = 0;
+            for (pass, program) in passes.iter().zip(programs) {
+                let target = 1 - source;
+                gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.targets[target].1));
+                gl.use_program(Some(program));
+                gl.bind_texture(glow::TEXTURE_2D, Some(self.targets[source].0));
+                gl.uniform_1_i32(gl.get_uniform_location(program, "u_image").as_ref(), 0);
+                gl.uniform_2_f32(
+                    gl.get_uniform_location(program, "u_resolution").as_ref(),
+                    width as f32,
+                    height as f32,
+                );
+                for (name, value) in pass.uniforms() {
+                    gl.uniform_1_f32(gl.get_uniform_location(program, name).as_ref(), value);
+                }
+                gl.draw_arrays(glow::TRIANGLES, 0, 3);
+                source = target;
+            }
+
+            // `source` now holds the output of the last pass, and is still bound
+            gl.read_pixels(
+                0,
+                0,
+                width,
+                height,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                glow::PixelPackData::Slice(&mut img.bytes),
+            );
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+            gl.bind_texture(glow::TEXTURE_2D, None);
+            gl.bind_vertex_array(None);
+            gl.use_program(None);
+        }
+        Ok(())
+    }
+
+    fn program(
+        &mut self,
+        gl: &Context,
+        version: ShaderVersion,
+        fragment: &str,
+    ) -> anyhow::Result<Program> {
+        if let Some(program) = self.programs.get(fragment) {
+            return Ok(*program);
+        }
+        let header = format!(
+            "{}{}",
+            version.version_declaration(),
+            if version.is_embedded() {
+                "precision highp float;\n"
+            } else {
+                ""
+            }
+        );
+        // SAFETY: plain shader compilation, everything is cleaned up on failure
+        let program = unsafe {
+            let program = gl.create_program().map_err(|e| anyhow!(e))?;
+            let mut shaders = vec![];
+            for (kind, source) in [
+                (
+                    glow::VERTEX_SHADER,
+                    format!("{header}{VERTEX_SHADER_SOURCE}"),
+                ),
+                (
+                    glow::FRAGMENT_SHADER,
+                    format!("{header}{FRAGMENT_SHADER_PREFIX}{fragment}"),
+                ),
+            ] {
+                let shader = gl.create_shader(kind).map_err(|e| anyhow!(e))?;
+                gl.shader_source(shader, &source);
+                gl.compile_shader(shader);
+                if !gl.get_shader_compile_status(shader) {
+                    let log = gl.get_shader_info_log(shader);
+                    gl.delete_shader(shader);
+                    gl.delete_program(program);
+                    return Err(anyhow!("failed to compile shader: {log}"));
+                }
+                gl.attach_shader(program, shader);
+                shaders.push(shader);
+            }
+            gl.link_program(program);
+            for shader in shaders {
+                gl.detach_shader(program, shader);
+                gl.delete_shader(shader);
+            }
+            if !gl.get_program_link_status(program) {
+                let log = gl.get_program_info_log(program);
+                gl.delete_program(program);
+                return Err(anyhow!("failed to link shader: {log}"));
+            }
+            program
+        };
+        self.programs.insert(fragment.to_owned(), program);
+        Ok(program)
+    }
+
+    /// Makes sure there are two textures of `size` to render between.
+    unsafe fn prepare_targets(&mut self, gl: &Context, size: [u32; 2]) -> anyhow::Result<()> {
+        if self.vertex_array.is_none() {
+            self.vertex_array = Some(gl.create_vertex_array().map_err(|e| anyhow!(e))?);
+        }
+        if self.size == size && !self.targets.is_empty() {
+            return Ok(());
+        }
+        self.delete_targets(gl);
+        for _ in 0..2 {
+            let texture = gl.create_texture().map_err(|e| anyhow!(e))?;
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MIN_FILTER,
+                glow::NEAREST as i32,
+            );
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_MAG_FILTER,
+                glow::NEAREST as i32,
+            );
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_WRAP_S,
+                glow::CLAMP_TO_EDGE as i32,
+            );
+            gl.tex_parameter_i32(
+                glow::TEXTURE_2D,
+                glow::TEXTURE_WRAP_T,
+                glow::CLAMP_TO_EDGE as i32,
+            );
+            gl.tex_image_2d(
+                glow::TEXTURE_2D,
+                0,
+                glow::RGBA8 as i32,
+                size[0] as i32,
+                size[1] as i32,
+                0,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                None,
+            );
+            let framebuffer = gl.create_framebuffer().map_err(|e| anyhow!(e))?;
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+            gl.framebuffer_texture_2d(
+                glow::FRAMEBUFFER,
+                glow::COLOR_ATTACHMENT0,
+                glow::TEXTURE_2D,
+                Some(texture),
+                0,
+            );
+            let status = gl.check_framebuffer_status(glow::FRAMEBUFFER);
+            self.targets.push((texture, framebuffer));
+            if status != glow::FRAMEBUFFER_COMPLETE {
+                gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+                self.delete_targets(gl);
+                return Err(anyhow!("framebuffer incomplete ({status:#x})"));
+            }
+        }
+        gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+        self.size = size;
+        Ok(())
+    }
+
+    unsafe fn delete_targets(&mut self, gl: &Context) {
+        for (texture, framebuffer) in self.targets.drain(..) {
+            gl.delete_framebuffer(framebuffer);
+            gl.delete_texture(texture);
+        }
+        self.size = [0, 0];
+    }
+
+    /// Frees everything, the pipeline can still be used again afterwards.
+    pub fn destroy(&mut self, gl: &Context) {
+        // SAFETY: all of these were created on `gl`
+        unsafe {
+            self.delete_targets(gl);
+            for (_, program) in self.programs.drain() {
+                gl.delete_program(program);
+            }
+            if let Some(vertex_array) = self.vertex_array.take() {
+                gl.delete_vertex_array(vertex_array);
+            }
+        }
+    }
+}
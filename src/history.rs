@@ -0,0 +1,396 @@
+use anyhow::anyhow;
+use chrono::Local;
+use egui::{RichText, Ui};
+
+use crate::{filter::FilterStep, image::MyImage, MyApp};
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// A deleted photo. Its pixels stay in the photo store until it is purged.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default)]
+#[serde(default)]
+pub struct TrashedPhoto {
+    pub photo: MyImage,
+    /// when it was deleted, in milliseconds since the unix epoch
+    pub deleted_ms: i64,
+    /// where it was in `photos`, so restoring puts it back in the same place
+    pub index: usize,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Trash {
+    pub photos: Vec<TrashedPhoto>,
+    /// how long photos stay in the trash before they are deleted for good
+    pub keep_days: u32,
+}
+
+impl Default for Trash {
+    fn default() -> Self {
+        Trash {
+            photos: vec![],
+            keep_days: 30,
+        }
+    }
+}
+
+/// Something done to `photos` that can be undone. Photos are found by id, since their
+/// indices shift around as others are deleted and moved.
+#[derive(Clone, Debug)]
+pub enum Change {
+    /// the photo went to the trash from `index`
+    Delete {
+        id: String,
+        index: usize,
+    },
+    Recipe {
+        id: String,
+        before: Vec<FilterStep>,
+        after: Vec<FilterStep>,
+    },
+    Move {
+        id: String,
+        from: usize,
+        to: usize,
+    },
+}
+
+/// The undo and redo stacks, which only last for the session.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+    /// the last recipe change is still going (a slider being dragged), keep adding to it
+    open: bool,
+}
+
+impl History {
+    pub fn push(&mut self, change: Change) {
+        self.redo.clear();
+        if let (
+            Change::Recipe { id, after, .. },
+            Some(Change::Recipe {
+                id: last_id,
+                after: last_after,
+                ..
+            }),
+        ) = (&change, self.undo.last_mut())
+        {
+            if self.open && id == last_id {
+                *last_after = after.clone();
+                return;
+            }
+        }
+        self.open = matches!(change, Change::Recipe { .. });
+        self.undo.push(change);
+    }
+
+    /// Stops the next recipe change being merged into the last one.
+    pub fn close(&mut self) {
+        self.open = false;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Drops the record of `id` going to the trash, for when it came back some other way.
+    fn forget_delete(&mut self, id: &str) {
+        for stack in [&mut self.undo, &mut self.redo] {
+            stack.retain(
+                |change| !matches!(change, Change::Delete { id: deleted, .. } if deleted == id),
+            );
+        }
+    }
+}
+
+impl MyApp {
+    fn photo_index(&self, id: &str) -> anyhow::Result<usize> {
+        self.photos
+            .iter()
+            .position(|p| p.id == id)
+            .ok_or(anyhow!("photo {id} is gone"))
+    }
+
+    /// The caches keyed by index are wrong once photos move around.
+    fn photos_moved(&mut self) {
+        self.developed = None;
+        self.jpeg_estimate = None;
+    }
+
+    /// Moves `photos[index]` to the trash, it can be restored until it is purged.
+    pub fn trash_photo(&mut self, index: usize) {
+        let id = self.photos[index].id.clone();
+        self.history.push(Change::Delete { id, index });
+        self.move_to_trash(index);
+    }
+
+    fn move_to_trash(&mut self, index: usize) {
+        let mut photo = self.photos.remove(index);
+        // no need to keep the pixels in memory, they're still in the store
+        photo.bytes = vec![];
+        self.trash.photos.push(TrashedPhoto {
+            photo,
+            deleted_ms: Local::now().timestamp_millis(),
+            index,
+        });
+        self.photos_moved();
+    }
+
+    /// Puts a photo back where it was deleted from, returning its new index.
+    pub fn restore_photo(&mut self, id: &str) -> anyhow::Result<usize> {
+        let position = self
+            .trash
+            .photos
+            .iter()
+            .position(|t| t.photo.id == id)
+            .ok_or(anyhow!("photo {id} is no longer in the trash"))?;
+        let trashed = self.trash.photos.remove(position);
+        let index = trashed.index.min(self.photos.len());
+        self.photos.insert(index, trashed.photo);
+        self.photos_moved();
+        self.save_options.image_index = index as i32;
+        Ok(index)
+    }
+
+    /// Restores a photo from the trash by hand, so undoing its delete later has nothing to do.
+    pub fn restore_from_trash(&mut self, id: &str) -> anyhow::Result<usize> {
+        let index = self.restore_photo(id)?;
+        self.history.forget_delete(id);
+        Ok(index)
+    }
+
+    /// Deletes `trash.photos[position]` for good, pixels and all.
+    pub fn delete_forever(&mut self, position: usize) {
+        let trashed = self.trash.photos.remove(position);
+        if !trashed.photo.id.is_empty() {
+            self.store.remove(&trashed.photo.id);
+        }
+        self.thumbnails.remove(&trashed.photo.id);
+    }
+
+    /// Deletes whatever has been in the trash longer than `keep_days`.
+    pub fn purge_trash(&mut self) {
+        let cutoff = Local::now().timestamp_millis() - self.trash.keep_days as i64 * DAY_MS;
+        for position in (0..self.trash.photos.len()).rev() {
+            if self.trash.photos[position].deleted_ms < cutoff {
+                self.delete_forever(position);
+            }
+        }
+    }
+
+    pub fn move_photo(&mut self, from: usize, to: usize) {
+        if from == to || to >= self.photos.len() {
+            return;
+        }
+        let id = self.photos[from].id.clone();
+        self.history.push(Change::Move { id, from, to });
+        self.reorder(from, to);
+    }
+
+    fn reorder(&mut self, from: usize, to: usize) {
+        let photo = self.photos.remove(from);
+        self.photos.insert(to, photo);
+        self.photos_moved();
+        self.save_options.image_index = to as i32;
+    }
+
+    /// Records a recipe edit made straight to `photos[index].recipe`. Called every frame
+    /// with what it was before, changes made while dragging are merged into one.
+    pub fn record_recipe(&mut self, index: usize, before: Vec<FilterStep>) {
+        let photo = &self.photos[index];
+        if photo.recipe != before {
+            self.history.push(Change::Recipe {
+                id: photo.id.clone(),
+                before,
+                after: photo.recipe.clone(),
+            });
+        }
+    }
+
+    fn set_recipe(&mut self, id: &str, recipe: Vec<FilterStep>) -> anyhow::Result<()> {
+        let index = self.photo_index(id)?;
+        self.photos[index].recipe = recipe;
+        self.save_options.image_index = index as i32;
+        Ok(())
+    }
+
+    /// Does `change` again, or reverses it when `undo` is set.
+    fn apply(&mut self, change: &Change, undo: bool) -> anyhow::Result<()> {
+        match (change, undo) {
+            (Change::Delete { id, .. }, true) => {
+                self.restore_photo(id)?;
+            }
+            (Change::Delete { id, .. }, false) => {
+                let index = self.photo_index(id)?;
+                self.move_to_trash(index);
+            }
+            (Change::Recipe { id, before, .. }, true) => self.set_recipe(id, before.clone())?,
+            (Change::Recipe { id, after, .. }, false) => self.set_recipe(id, after.clone())?,
+            (Change::Move { id, from, to }, undo) => {
+                let index = self.photo_index(id)?;
+                let to = if undo { *from } else { *to };
+                self.reorder(index, to.min(self.photos.len() - 1));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn undo(&mut self) {
+        self.history.close();
+        if let Some(change) = self.history.undo.pop() {
+            match self.apply(&change, true) {
+                Ok(()) => self.history.redo.push(change),
+                // the photo was deleted for good, there's nothing to undo
+                Err(e) => log::warn!("couldn't undo {change:?}: {e}"),
+            }
+        }
+    }
+
+    pub fn redo(&mut self) {
+        self.history.close();
+        if let Some(change) = self.history.redo.pop() {
+            match self.apply(&change, false) {
+                Ok(()) => self.history.undo.push(change),
+                Err(e) => log::warn!("couldn't redo {change:?}: {e}"),
+            }
+        }
+    }
+
+    pub fn render_undo_ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(
+                    self.history.can_undo(),
+                    egui::Button::new(egui_phosphor::regular::ARROW_COUNTER_CLOCKWISE),
+                )
+                .on_hover_text("undo (ctrl+z)")
+                .clicked()
+            {
+                self.undo();
+            }
+            if ui
+                .add_enabled(
+                    self.history.can_redo(),
+                    egui::Button::new(egui_phosphor::regular::ARROW_CLOCKWISE),
+                )
+                .on_hover_text("redo (ctrl+shift+z)")
+                .clicked()
+            {
+                self.redo();
+            }
+        });
+    }
+
+    pub fn render_trash_ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("keep deleted photos for");
+            if ui
+                .add(
+                    egui::DragValue::new(&mut self.trash.keep_days)
+                        .range(1..=365)
+                        .suffix(" days"),
+                )
+                .changed()
+            {
+                self.purge_trash();
+            }
+        });
+        if self.trash.photos.is_empty() {
+            ui.label(RichText::new("the trash is empty").italics().small());
+            return;
+        }
+
+        let now = Local::now().timestamp_millis();
+        let mut restore = None;
+        let mut delete = None;
+        // most recently deleted first
+        for (position, trashed) in self.trash.photos.iter().enumerate().rev() {
+            ui.horizontal(|ui| {
+                if ui.small_button("restore").clicked() {
+                    restore = Some(trashed.photo.id.clone());
+                }
+                if ui
+                    .small_button(egui_phosphor::regular::TRASH)
+                    .on_hover_text("delete forever")
+                    .clicked()
+                {
+                    delete = Some(position);
+                }
+                let days_left = self.trash.keep_days as i64 - (now - trashed.deleted_ms) / DAY_MS;
+                ui.label(&trashed.photo.name)
+                    .on_hover_text(format!("deleted for good in {} day/s", days_left.max(0)));
+            });
+        }
+        if let Some(id) = restore {
+            if let Err(e) = self.restore_from_trash(&id) {
+                log::warn!("{e}");
+            }
+        }
+        if let Some(position) = delete {
+            self.delete_forever(position);
+        }
+        if ui.button("empty trash").clicked() {
+            for position in (0..self.trash.photos.len()).rev() {
+                self.delete_forever(position);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(ids: &[&str]) -> MyApp {
+        MyApp {
+            photos: ids
+                .iter()
+                .map(|id| MyImage {
+                    id: id.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn ids(app: &MyApp) -> Vec<&str> {
+        app.photos.iter().map(|p| p.id.as_str()).collect()
+    }
+
+    #[test]
+    fn undo_and_redo_a_delete() {
+        let mut app = app(&["a", "b", "c"]);
+        app.trash_photo(1);
+        assert_eq!(ids(&app), ["a", "c"]);
+        app.undo();
+        assert_eq!(ids(&app), ["a", "b", "c"]);
+        assert!(app.trash.photos.is_empty());
+        app.redo();
+        assert_eq!(ids(&app), ["a", "c"]);
+        assert_eq!(app.trash.photos.len(), 1);
+    }
+
+    #[test]
+    fn restoring_from_the_trash_forgets_the_delete() {
+        let mut app = app(&["a", "b", "c"]);
+        app.move_photo(2, 0);
+        app.trash_photo(1);
+        assert_eq!(ids(&app), ["c", "b"]);
+        app.restore_from_trash("a").unwrap();
+        assert_eq!(ids(&app), ["c", "a", "b"]);
+
+        // the delete is gone, so undo goes straight to the move
+        app.undo();
+        assert_eq!(ids(&app), ["a", "b", "c"]);
+        assert!(!app.history.can_undo());
+        app.redo();
+        assert_eq!(ids(&app), ["c", "a", "b"]);
+        assert!(!app.history.can_redo());
+    }
+}
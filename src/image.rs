@@ -1,30 +1,48 @@
-use std::io::{BufWriter, Cursor, Read};
+use std::io::Cursor;
 
-use async_zip::{base::write::ZipFileWriter, ZipEntryBuilder};
-use chrono::{DateTime, Local, TimeZone, Utc};
-use eframe::glow::{Buffer, HasContext, Program, Shader};
-use ::image::{DynamicImage, ImageBuffer, Pixel, Rgba};
-use anyhow::anyhow;
-use egui::{
-    epaint::{image, TextureManager},
-    load::SizedTexture,
-    CentralPanel, Context, Image, LayerId,
+use ::image::{
+    codecs::png::{CompressionType, FilterType as PngFilter, PngEncoder},
+    DynamicImage, ImageBuffer, ImageFormat, Rgba,
 };
+use anyhow::anyhow;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use egui::Context;
+use jpeg_encoder::SamplingFactor;
 use pollster::FutureExt;
+use strum_macros::EnumIter;
 // use ::image::{DynamicImage, ImageBuffer};
-use crate::MyApp;
-use wasm_bindgen::JsCast;
-use web_sys::{window, Navigator};
+use crate::{
+    exif::Exif,
+    file_name::export_names,
+    file_stuff::{export_target, zip_files, ExportFile},
+    filter::{Filter, FilterStep},
+    gpu::Pass,
+    MyApp,
+};
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 #[serde(default)]
 pub struct MyImage {
     pub width: u32,
     pub height: u32,
+    /// for photos, this is the capture as it came from the source, before any filters.
+    /// Photos keep their pixels in the [`crate::photo_store::PhotoStore`] rather than the app
+    /// state, so this is empty until [`MyApp::load_photo`] fetches them (it is still read
+    /// from state saved before that)
+    #[serde(skip_serializing)]
     pub bytes: Vec<u8>,
     pub save: bool,
     pub name: String,
-    pub del: bool,
+    /// the filters the photo is shown and exported with, see [`MyApp::develop`]
+    pub recipe: Vec<FilterStep>,
+    /// when it was captured, in milliseconds since the unix epoch. 0 for photos from
+    /// before this was kept, see [`MyImage::taken`]
+    #[serde(default)]
+    pub taken_ms: i64,
+    /// the source the photo was taken with
+    pub device: String,
+    /// the key of the photo in the photo store, empty until it has been stored
+    pub id: String,
 }
 impl Default for MyImage {
     fn default() -> Self {
@@ -35,142 +53,619 @@ impl Default for MyImage {
             width: 0,
             height: 0,
             bytes: vec![],
-            del: false,
+            recipe: vec![],
+            taken_ms: local.timestamp_millis(),
+            device: String::new(),
+            id: String::new(),
         }
     }
 }
 
-impl MyApp {
-    pub fn process_image(&self, img: &mut MyImage) {
-        const VERTEX_SHADER_SOURCE: &str = r#"#version 330 core
-        const vec2 coords[] = vec2[3] (
-            vec2(-1.0, -1.0),
-            vec2(3.0, -1.0),
-            vec2(-1.0, 3.0)
+impl From<DynamicImage> for MyImage {
+    fn from(img: DynamicImage) -> Self {
+        let img = img.into_rgba8();
+        MyImage {
+            width: img.width(),
+            height: img.height(),
+            bytes: img.into_raw(),
+            ..Default::default()
+        }
+    }
+}
+
+impl MyImage {
+    /// Number of bytes an rgba image of this size takes up.
+    pub fn rgba_len(width: u32, height: u32) -> usize {
+        width as usize * height as usize * 4
+    }
+
+    /// Whether the pixels are here, rather than only in the photo store.
+    pub fn is_loaded(&self) -> bool {
+        self.width > 0
+            && self.height > 0
+            && self.bytes.len() == MyImage::rgba_len(self.width, self.height)
+    }
+
+    /// The pixels as a png, which is how they are kept in the photo store. Compressed quickly
+    /// rather than well, since it happens every time a photo is taken.
+    pub fn compress(&self) -> anyhow::Result<Vec<u8>> {
+        let mut png = vec![];
+        let encoder =
+            PngEncoder::new_with_quality(&mut png, CompressionType::Fast, PngFilter::Adaptive);
+        self.to_dynamic()?.write_with_encoder(encoder)?;
+        Ok(png)
+    }
+
+    /// When the photo was captured. Older photos only have it in their name.
+    pub fn taken(&self) -> DateTime<Local> {
+        if self.taken_ms == 0 {
+            if let Some(taken) = NaiveDateTime::parse_from_str(&self.name, "%Y-%m-%d %H-%M-%S")
+                .ok()
+                .and_then(|t| t.and_local_timezone(Local).earliest())
+            {
+                return taken;
+            }
+        }
+        Local
+            .timestamp_millis_opt(self.taken_ms)
+            .earliest()
+            .unwrap_or_else(Local::now)
+    }
+
+    pub fn to_dynamic(&self) -> anyhow::Result<DynamicImage> {
+        let expected = MyImage::rgba_len(self.width, self.height);
+        let buf: ImageBuffer<Rgba<u8>, Vec<u8>> =
+            ImageBuffer::from_raw(self.width, self.height, self.bytes.clone())
+                // from_raw is happy with too many bytes, but then the size is wrong too
+                .filter(|_| self.bytes.len() == expected)
+                .ok_or(anyhow!(
+                    "{}x{} image has {} bytes, expected {expected}",
+                    self.width,
+                    self.height,
+                    self.bytes.len(),
+                ))?;
+        Ok(DynamicImage::from(buf))
+    }
+
+    /// A copy scaled to `[width, height]`, or just a copy if it is already that size.
+    pub fn resized(&self, size: [u32; 2]) -> anyhow::Result<MyImage> {
+        if size == [self.width, self.height] {
+            return Ok(self.clone());
+        }
+        let img = self.to_dynamic()?.resize_exact(
+            size[0],
+            size[1],
+            ::image::imageops::FilterType::Triangle,
         );
-        void main()
-        {
-            gl_Position = vec4(coords[gl_VertexID], 0., 1.);
-        }
-        "#;
-        const FRAGMENT_SHADER_SOURCE: &str = r#"#version 330 core
-            uniform vec2 iResolution;
-            uniform float iTime;
-            out vec4 color;
-            void main()
+        Ok(MyImage {
+            name: self.name.clone(),
+            save: self.save,
+            recipe: self.recipe.clone(),
+            taken_ms: self.taken_ms,
+            device: self.device.clone(),
+            id: self.id.clone(),
+            ..img.into()
+        })
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, EnumIter, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ExportFormat {
+    Jpeg,
+    Png,
+    /// always lossless, the `image` crate can't write lossy webp
+    WebP,
+    Tiff,
+    Bmp,
+    Qoi,
+}
+
+/// How the jpeg's colour channels are stored relative to its brightness.
+#[derive(serde::Deserialize, serde::Serialize, EnumIter, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Subsampling {
+    /// full resolution colour
+    S444,
+    /// half the colour resolution horizontally
+    S422,
+    /// half the colour resolution both ways, the usual for photos
+    S420,
+}
+
+impl Subsampling {
+    pub fn name(&self) -> &str {
+        match self {
+            Subsampling::S444 => "4:4:4",
+            Subsampling::S422 => "4:2:2",
+            Subsampling::S420 => "4:2:0",
+        }
+    }
+
+    fn sampling_factor(&self) -> SamplingFactor {
+        match self {
+            Subsampling::S444 => SamplingFactor::R_4_4_4,
+            Subsampling::S422 => SamplingFactor::R_4_2_2,
+            Subsampling::S420 => SamplingFactor::R_4_2_0,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Debug)]
+#[serde(default)]
+pub struct JpegOptions {
+    /// 1 to 100
+    pub quality: u8,
+    pub subsampling: Subsampling,
+    pub progressive: bool,
+}
+
+impl Default for JpegOptions {
+    fn default() -> Self {
+        JpegOptions {
+            quality: 90,
+            subsampling: Subsampling::S420,
+            progressive: false,
+        }
+    }
+}
+
+impl JpegOptions {
+    /// `exif` is the payload of an APP1 segment, see [`Exif::to_app1`].
+    pub fn encode(&self, img: &DynamicImage, exif: Option<&[u8]>) -> anyhow::Result<Vec<u8>> {
+        let (width, height) = (
+            u16::try_from(img.width()).map_err(|_| anyhow!("too wide for a jpeg"))?,
+            u16::try_from(img.height()).map_err(|_| anyhow!("too tall for a jpeg"))?,
+        );
+        let mut bytes = vec![];
+        let mut encoder = jpeg_encoder::Encoder::new(&mut bytes, self.quality.clamp(1, 100));
+        encoder.set_sampling_factor(self.subsampling.sampling_factor());
+        encoder.set_progressive(self.progressive);
+        if let Some(exif) = exif {
+            encoder.add_app_segment(1, exif)?;
+        }
+        // jpeg has no alpha channel
+        encoder.encode(&img.to_rgb8(), width, height, jpeg_encoder::ColorType::Rgb)?;
+        Ok(bytes)
+    }
+}
+
+impl ExportFormat {
+    pub fn name(&self) -> &str {
+        match self {
+            ExportFormat::Jpeg => "jpeg",
+            ExportFormat::Png => "png",
+            ExportFormat::WebP => "webp (lossless)",
+            ExportFormat::Tiff => "tiff",
+            ExportFormat::Bmp => "bmp",
+            ExportFormat::Qoi => "qoi",
+        }
+    }
+
+    pub fn extension(&self) -> &str {
+        match self {
+            ExportFormat::Jpeg => "jpg",
+            ExportFormat::Png => "png",
+            ExportFormat::WebP => "webp",
+            ExportFormat::Tiff => "tiff",
+            ExportFormat::Bmp => "bmp",
+            ExportFormat::Qoi => "qoi",
+        }
+    }
+
+    /// Formats that are already compressed aren't worth deflating again.
+    pub fn deflate(&self) -> bool {
+        match self {
+            ExportFormat::Jpeg | ExportFormat::Png | ExportFormat::WebP => false,
+            ExportFormat::Tiff | ExportFormat::Bmp | ExportFormat::Qoi => true,
+        }
+    }
+
+    /// Only jpegs get `exif`, the other formats are written without metadata.
+    pub fn encode(
+        &self,
+        img: &DynamicImage,
+        jpeg: &JpegOptions,
+        exif: Option<&[u8]>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        let mut cursor = Cursor::new(&mut bytes);
+        match self {
+            ExportFormat::Jpeg => return jpeg.encode(img, exif),
+            ExportFormat::Png => img.write_to(&mut cursor, ImageFormat::Png)?,
+            ExportFormat::WebP => img.write_to(&mut cursor, ImageFormat::WebP)?,
+            ExportFormat::Tiff => img.write_to(&mut cursor, ImageFormat::Tiff)?,
+            ExportFormat::Bmp => img.write_to(&mut cursor, ImageFormat::Bmp)?,
+            ExportFormat::Qoi => img.write_to(&mut cursor, ImageFormat::Qoi)?,
+        }
+        Ok(bytes)
+    }
+}
+
+/// Indices of the photos that go into an export: the ones ticked to be saved. Deleted
+/// photos are in the trash rather than `photos`, so they never count.
+pub fn selected_for_export(photos: &[MyImage]) -> Vec<usize> {
+    photos
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.save)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// The buttons for changing which photos are ticked to be saved.
+#[derive(EnumIter, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Selection {
+    All,
+    None,
+    Invert,
+}
+
+impl Selection {
+    pub fn name(&self) -> &str {
+        match self {
+            Selection::All => "select all",
+            Selection::None => "select none",
+            Selection::Invert => "invert selection",
+        }
+    }
+
+    pub fn apply(&self, photos: &mut [MyImage]) {
+        for p in photos {
+            p.save = match self {
+                Selection::All => true,
+                Selection::None => false,
+                Selection::Invert => !p.save,
+            };
+        }
+    }
+}
+
+/// A byte count in kB/MB, for showing to people.
+pub fn format_size(bytes: usize) -> String {
+    match bytes {
+        0..=999 => format!("{bytes} B"),
+        1000..=999_999 => format!("{:.1} kB", bytes as f32 / 1e3),
+        _ => format!("{:.1} MB", bytes as f32 / 1e6),
+    }
+}
+
+impl MyApp {
+    /// Runs a filter chain over `img`, on the gpu when there is a gl context and
+    /// on the cpu otherwise.
+    pub fn process_image(&mut self, img: &mut MyImage, filters: &[FilterStep]) {
+        let passes = filters
+            .iter()
+            .filter(|p| p.enabled)
+            .map(|p| p as &dyn Pass)
+            .collect::<Vec<&dyn Pass>>();
+        if passes.is_empty() {
+            return;
+        }
+        if !img.is_loaded() {
+            // a problem with this image, not with the gpu, so don't give up on it
+            log::warn!(
+                "not processing a {}x{} image with {} bytes",
+                img.width,
+                img.height,
+                img.bytes.len()
+            );
+            return;
+        }
+        if let Some(gl) = &self.gl {
+            match self.gl_pipeline.run(gl, &passes, img) {
+                Ok(()) => return,
+                Err(e) => {
+                    // it won't go any better next frame, so stick to the cpu from now on
+                    log::warn!("gpu processing failed, falling back to the cpu: {e:?}");
+                    self.gl_pipeline.destroy(gl);
+                    self.gl = None;
+                }
+            }
+        }
+        filters.apply(img);
+    }
+
+    /// Puts `photos[index]` into the photo store, giving it an id first if it needs one.
+    pub fn store_photo(&mut self, index: usize) -> anyhow::Result<()> {
+        if self.photos[index].id.is_empty() {
+            let base = self.photos[index].taken_ms.to_string();
+            let mut id = base.clone();
+            let mut n = 2;
+            // ids in the trash are still taken, the photo might be restored
+            while self
+                .photos
+                .iter()
+                .chain(self.trash.photos.iter().map(|t| &t.photo))
+                .any(|p| p.id == id)
             {
-                vec2 uv = gl_FragCoord.xy / iResolution.xy;
-                vec2 pos = (uv.xy-0.5);
-                vec2 cir = ((pos.xy*pos.xy+sin(uv.x*18.0+iTime)/25.0*sin(uv.y*7.0+iTime*1.5)/1.0)+uv.x*sin(iTime)/16.0+uv.y*sin(iTime*1.2)/16.0);
-                float circles = (sqrt(abs(cir.x+cir.y*0.5)*25.0)*5.0);
-                color = vec4(sin(circles*1.25+2.0),abs(sin(circles*1.0-1.0)-sin(circles)),abs(sin(circles)*1.0),1.0);
-            }"#;
-
-    }
-
-    pub fn capture_frame(&self, full_quality: bool) -> anyhow::Result<(MyImage)> {
-        let video = window()
-            .ok_or(anyhow!("no window"))?
-            .document()
-            .ok_or(anyhow!("no document"))?
-            .get_element_by_id("videoElement")
-            .ok_or(anyhow!("video element not found"))?;
-        let canvas = window()
-            .ok_or(anyhow!("no window"))?
-            .document()
-            .ok_or(anyhow!("no document"))?
-            .get_element_by_id("canvas")
-            .ok_or(anyhow!("video element not found"))?;
-        let devi = if full_quality { 1 } else { 5 };
-
-        canvas.set_attribute("width", &(video.client_width()).to_string());
-        canvas.set_attribute("height", &(video.client_height()).to_string());
-
-        let video: web_sys::HtmlVideoElement = video
-            .dyn_into::<web_sys::HtmlVideoElement>()
-            .map_err(|_| ())
-            .unwrap();
-        let canvas: web_sys::HtmlCanvasElement = canvas
-            .dyn_into::<web_sys::HtmlCanvasElement>()
-            .map_err(|_| ())
-            .unwrap();
-
-        let context = canvas
-            .get_context("2d")
-            .unwrap()
-            .unwrap()
-            .dyn_into::<web_sys::CanvasRenderingContext2d>()
-            .unwrap();
-
-        context
-            .draw_image_with_html_video_element_and_dw_and_dh(
-                &video,
-                0.0,
-                0.0,
-                (canvas.width() as i32 / devi) as f64,
-                (canvas.height() as i32 / devi) as f64,
-            )
-            .ok()
-            .ok_or(anyhow!("failed to write image"))?;
-
-        let data = context
-            .get_image_data(0.0, 0.0, (canvas.width() as i32 / devi) as f64, (canvas.width() as i32 / devi) as f64)
-            .ok()
-            .ok_or(anyhow!("failed to capture"))?;
-        let local: DateTime<Local> = Local::now();
-        Ok(
-            MyImage {
-                name: local.format("%Y-%m-%d %H-%M-%S").to_string(),
-                height: (canvas.width() / devi as u32),
-                width: (canvas.width() / devi as u32),
-                bytes: data.data().to_vec(),
-                save: true,
-                del: false,
-            },
-        )
-    }
-
-    pub fn save_photos(&mut self, ctx: &Context) -> anyhow::Result<Vec<u8>> {
-        self.save_photos_async(ctx).block_on()
-    }
-
-    async fn save_photos_async(&mut self, ctx: &Context) -> anyhow::Result<Vec<u8>> {
-        let mut file: Vec<u8> = vec![];
-        let mut writer = ZipFileWriter::new(&mut file);
-        let mut photo_vec: Vec<DynamicImage> = vec![];
-        for p in &self.photos {
-            let buf: ImageBuffer<Rgba<u8>, Vec<_>> = ImageBuffer::from_fn(p.width, p.height, |x,y| {
-                Rgba::from(
-                    [p.bytes[4 * (x + y * p.height as u32) as usize],
-                        p.bytes[4 * (x + y * p.height as u32) as usize + 1],
-                        p.bytes[4 * (x + y * p.height as u32) as usize + 2],
-                        p.bytes[4 * (x + y * p.height as u32) as usize + 3],
-                    ])
-            });
-            let img = DynamicImage::from(buf);
-            
-            let builder = ZipEntryBuilder::new((p.name).clone().into(), async_zip::Compression::Deflate);
-            
-            if self.save_options.jpg {
-                let mut a = vec![];
-                let mut buf2 : Cursor<&mut Vec<u8>> = Cursor::new(&mut a);
-                let img3 = img.to_rgb8();
-                img3.write_to(&mut buf2, ::image::ImageFormat::Jpeg)?;
-                writer.write_entry_whole(builder, &buf2.bytes().map(|x| x.unwrap_or(0)).collect::<Vec<u8>>()).await?;
+                id = format!("{base}-{n}");
+                n += 1;
+            }
+            self.photos[index].id = id;
+        }
+        let png = self.photos[index].compress()?;
+        self.store.put(&self.photos[index].id, png);
+        Ok(())
+    }
+
+    /// Fetches the pixels of `photos[index]` from the photo store if they aren't here yet.
+    /// `Ok(false)` means they're on their way, try again next frame.
+    pub fn load_photo(&mut self, index: usize) -> anyhow::Result<bool> {
+        let photo = &mut self.photos[index];
+        if photo.is_loaded() {
+            return Ok(true);
+        }
+        let Some(png) = self.store.get(&photo.id) else {
+            return Ok(false);
+        };
+        let img: MyImage = ::image::load_from_memory_with_format(&png?, ImageFormat::Png)?.into();
+        photo.width = img.width;
+        photo.height = img.height;
+        photo.bytes = img.bytes;
+        Ok(true)
+    }
+
+    /// `photos[index]` with its recipe applied, the photo itself is left untouched.
+    pub fn develop(&mut self, index: usize) -> anyhow::Result<MyImage> {
+        if !self.load_photo(index)? {
+            return Err(anyhow!("still loading {}", self.photos[index].name));
+        }
+        let mut img = self.photos[index].clone();
+        let recipe = std::mem::take(&mut img.recipe);
+        self.process_image(&mut img, &recipe);
+        img.recipe = recipe;
+        Ok(img)
+    }
+
+    /// How big the photo on screen in the save tab comes out as a jpeg with the current
+    /// options. Only re-encoded when the photo, its recipe or the options change.
+    pub fn jpeg_size_estimate(&mut self) -> Option<usize> {
+        let (index, recipe, img) = self.developed.as_ref()?;
+        let options = &self.save_options.jpeg;
+        match &self.jpeg_estimate {
+            Some((i, r, o, size)) if i == index && r == recipe && o == options => Some(*size),
+            _ => {
+                let size = options.encode(&img.to_dynamic().ok()?, None).ok()?.len();
+                self.jpeg_estimate = Some((*index, recipe.clone(), options.clone(), size));
+                Some(size)
+            }
+        }
+    }
+
+    pub fn capture_frame(&mut self, full_quality: bool) -> anyhow::Result<MyImage> {
+        let resolution = if full_quality {
+            self.source_options.photo
+        } else {
+            self.source_options.preview
+        };
+        Ok(self.source.next_frame(resolution)?.image)
+    }
+
+    /// Encodes the photos at `indices` in every chosen format.
+    pub fn export_files(&mut self, indices: &[usize]) -> anyhow::Result<Vec<ExportFile>> {
+        // ask for everything at once, rather than waiting on each photo in turn
+        let mut loading = 0;
+        for &i in indices {
+            if !self.load_photo(i)? {
+                loading += 1;
+            }
+        }
+        if loading > 0 {
+            return Err(anyhow!(
+                "still loading {loading} photo/s, try again in a moment"
+            ));
+        }
+
+        let mut files = vec![];
+        let formats = self.save_options.formats();
+        let names = export_names(
+            &self.save_options.name_template,
+            &self.save_options.session_prefix,
+            &self.photos,
+            indices,
+        );
+        for (&i, name) in indices.iter().zip(names) {
+            let p = self.develop(i)?;
+            let img = p.to_dynamic()?;
+            let exif = (!self.save_options.strip_metadata).then(|| Exif::for_photo(&p).to_app1());
+
+            if self.save_options.raw_png {
+                files.push(ExportFile {
+                    name: format!("{name} raw.png"),
+                    bytes: ExportFormat::Png.encode(
+                        &self.photos[i].to_dynamic()?,
+                        &self.save_options.jpeg,
+                        None,
+                    )?,
+                    deflate: false,
+                });
+            }
+
+            for format in &formats {
+                files.push(ExportFile {
+                    name: format!("{name}.{}", format.extension()),
+                    bytes: format.encode(&img, &self.save_options.jpeg, exif.as_deref())?,
+                    deflate: format.deflate(),
+                });
+            }
+        }
+        Ok(files)
+    }
+
+    /// Exports the selected photos, zipped or as loose files, and says where they went.
+    pub fn export_photos(&mut self) -> anyhow::Result<String> {
+        let target = export_target(&self.save_options.directory)?;
+        let files = self.export_files(&selected_for_export(&self.photos))?;
+        if files.is_empty() {
+            return Err(anyhow!(
+                "there is nothing to save, pick at least one format"
+            ));
+        }
+        let count = files.len();
+        // the browser only lets us start one download at a time
+        if self.save_options.zip || cfg!(target_arch = "wasm32") {
+            let local: DateTime<Local> = Local::now();
+            let name = format!("{} Photos.zip", local.format("%Y-%m-%d %H-%M-%S"));
+            target.write(&name, zip_files(files).block_on()?)?;
+            Ok(format!("saved {name} to {}", target.describe()))
+        } else {
+            for file in files {
+                target.write(&file.name, file.bytes)?;
             }
-            photo_vec.push(img);
+            Ok(format!("saved {count} files to {}", target.describe()))
         }
+    }
 
-        return Ok(writer.close().await?.to_vec());
+    /// Saves one photo straight away. Only zipped on the web, and only when it comes out as
+    /// more than one file.
+    pub fn export_photo(&mut self, index: usize) -> anyhow::Result<String> {
+        let target = export_target(&self.save_options.directory)?;
+        let files = self.export_files(&[index])?;
+        if files.is_empty() {
+            return Err(anyhow!(
+                "there is nothing to save, pick at least one format"
+            ));
+        }
+        // the browser only lets us start one download at a time
+        if files.len() > 1 && cfg!(target_arch = "wasm32") {
+            let options = &self.save_options;
+            let name = export_names(
+                &options.name_template,
+                &options.session_prefix,
+                &self.photos,
+                &[index],
+            );
+            let name = format!("{}.zip", name[0]);
+            target.write(&name, zip_files(files).block_on()?)?;
+            return Ok(format!("saved {name} to {}", target.describe()));
+        }
+        let names = files
+            .iter()
+            .map(|f| f.name.clone())
+            .collect::<Vec<String>>()
+            .join(", ");
+        for file in files {
+            target.write(&file.name, file.bytes)?;
+        }
+        Ok(format!("saved {names} to {}", target.describe()))
     }
+
     pub fn take_photo(&mut self, ctx: &Context) -> anyhow::Result<()> {
         let image = self.capture_frame(true);
-        if let Ok(image) = image {
+        if let Ok(mut image) = image {
+            // the filters are kept with the photo rather than baked in, so they can be changed later
+            image.recipe = self.filters.clone();
+            image.device = self.source.name();
+            // sources can hand back a frame decoded (and defaulted) a while ago
+            image.taken_ms = Local::now().timestamp_millis();
             self.save_options.image_index = self.photos.len() as i32 - 1;
 
             self.photos.push(image);
+            self.store_photo(self.photos.len() - 1)?;
         };
-        
-        return Ok(());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, len: usize) -> MyImage {
+        MyImage {
+            width,
+            height,
+            bytes: vec![0; len],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rgba_len_is_four_bytes_a_pixel() {
+        assert_eq!(MyImage::rgba_len(3, 2), 24);
+        assert_eq!(MyImage::rgba_len(0, 5), 0);
+        // big enough to overflow a u32
+        assert_eq!(MyImage::rgba_len(40_000, 30_000), 4_800_000_000);
+    }
+
+    #[test]
+    fn to_dynamic_keeps_the_size() {
+        let dynamic = image(3, 2, 24).to_dynamic().unwrap();
+        assert_eq!((dynamic.width(), dynamic.height()), (3, 2));
+        assert!(image(3, 2, 24).is_loaded());
+    }
+
+    #[test]
+    fn to_dynamic_rejects_the_wrong_number_of_bytes() {
+        assert!(image(3, 2, 23).to_dynamic().is_err());
+        assert!(image(3, 2, 28).to_dynamic().is_err());
+        // the size swapped around is still fine, but a square of the same area isn't
+        assert!(image(2, 3, 24).to_dynamic().is_ok());
+        assert!(image(3, 3, 24).to_dynamic().is_err());
+        assert!(!image(3, 2, 28).is_loaded());
+    }
+
+    fn photos(save: &[bool]) -> Vec<MyImage> {
+        save.iter()
+            .enumerate()
+            .map(|(i, &save)| MyImage {
+                name: format!("photo {i}"),
+                save,
+                ..image(2, 2, 16)
+            })
+            .collect()
+    }
+
+    fn ticked(photos: &[MyImage]) -> Vec<bool> {
+        photos.iter().map(|p| p.save).collect()
+    }
+
+    #[test]
+    fn selected_for_export_is_the_ticked_photos() {
+        assert_eq!(
+            selected_for_export(&photos(&[true, false, true, true, false])),
+            vec![0, 2, 3]
+        );
+        assert!(selected_for_export(&photos(&[false, false])).is_empty());
+        assert!(selected_for_export(&[]).is_empty());
+    }
+
+    #[test]
+    fn select_all_none_and_invert() {
+        let mut photos = photos(&[true, false, true]);
+        Selection::Invert.apply(&mut photos);
+        assert_eq!(ticked(&photos), [false, true, false]);
+        assert_eq!(selected_for_export(&photos), vec![1]);
+        Selection::All.apply(&mut photos);
+        assert_eq!(ticked(&photos), [true; 3]);
+        Selection::None.apply(&mut photos);
+        assert_eq!(ticked(&photos), [false; 3]);
+        assert!(selected_for_export(&photos).is_empty());
+    }
+
+    #[test]
+    fn export_files_only_has_the_chosen_photos() {
+        let mut app = MyApp {
+            photos: photos(&[true, false, true]),
+            ..Default::default()
+        };
+        app.save_options.name_template = "{name}".to_owned();
+        app.save_options.session_prefix = String::new();
+        app.save_options.jpg = true;
+        app.save_options.png = true;
+        app.save_options.raw_png = false;
+        for i in 0..app.photos.len() {
+            app.store_photo(i).unwrap();
+            // only in the store, like photos from an earlier session
+            app.photos[i].bytes = vec![];
+        }
+
+        let files = app.export_files(&selected_for_export(&app.photos)).unwrap();
+        let names = files.iter().map(|f| f.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(
+            names,
+            ["photo 0.jpg", "photo 0.png", "photo 2.jpg", "photo 2.png"]
+        );
+        assert!(files.iter().all(|f| !f.bytes.is_empty()));
     }
 }
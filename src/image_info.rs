@@ -1,20 +1,30 @@
-use egui::{Color32, Stroke, Ui};
-use egui_plot::{Line, PlotPoints};
+use std::{borrow::Cow, collections::HashMap, f64::consts::TAU};
+
+use anyhow::anyhow;
+use egui::{ecolor::Hsva, pos2, Color32, Pos2, Rect, RichText, Sense, Stroke, Ui};
+use egui_plot::{Legend, Line, LineStyle};
 use oklab::Rgb;
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
-use crate::{image::MyImage, MyApp};
+use crate::{
+    filter::{luma, FilterStep},
+    image::MyImage,
+    MyApp,
+};
+
+/// The range of bin counts, the same one for every histogram type.
+const BINS: std::ops::RangeInclusive<usize> = 16..=256;
 
-#[derive(serde::Deserialize, serde::Serialize, EnumIter, PartialEq, Eq)]
+#[derive(serde::Deserialize, serde::Serialize, EnumIter, PartialEq, Eq, Clone, Copy, Default)]
 pub enum HistogramType {
+    #[default]
     Rgb,
     OkLab,
-}
-impl Default for HistogramType {
-    fn default() -> Self {
-        HistogramType::Rgb
-    }
+    Hsv,
+    Hsl,
+    YCbCr,
+    Luminance,
 }
 
 impl HistogramType {
@@ -22,74 +32,828 @@ impl HistogramType {
         match self {
             HistogramType::Rgb => "RGB",
             HistogramType::OkLab => "OkLab",
+            HistogramType::Hsv => "HSV",
+            HistogramType::Hsl => "HSL",
+            HistogramType::YCbCr => "YCbCr",
+            HistogramType::Luminance => "luminance",
+        }
+    }
+
+    /// Which channels are scaled together. Channels in the same group can be compared
+    /// against each other, hue and saturation (say) can't so they each get their own.
+    fn scale_groups(&self) -> [usize; 3] {
+        match self {
+            HistogramType::Rgb | HistogramType::Luminance => [0, 0, 0],
+            HistogramType::OkLab => [0, 0, 1],
+            HistogramType::Hsv | HistogramType::Hsl | HistogramType::YCbCr => [0, 1, 2],
         }
     }
 }
 
+/// The weights YCbCr is worked out with.
+#[derive(serde::Deserialize, serde::Serialize, EnumIter, PartialEq, Eq, Clone, Copy, Default)]
+pub enum YCbCrMatrix {
+    /// standard definition video, and jpeg
+    #[default]
+    Bt601,
+    /// hd video
+    Bt709,
+}
+
+impl YCbCrMatrix {
+    fn name(&self) -> &str {
+        match self {
+            YCbCrMatrix::Bt601 => "BT.601",
+            YCbCrMatrix::Bt709 => "BT.709",
+        }
+    }
+
+    /// Full range `[y, cb, cr]` (like jpeg), all in `0.0..=1.0`.
+    fn ycbcr(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let (kr, kb) = match self {
+            YCbCrMatrix::Bt601 => (0.299, 0.114),
+            YCbCrMatrix::Bt709 => (0.2126, 0.0722),
+        };
+        let y = kr * rgb[0] + (1.0 - kr - kb) * rgb[1] + kb * rgb[2];
+        [
+            y,
+            0.5 + (rgb[2] - y) / (2.0 * (1.0 - kb)),
+            0.5 + (rgb[0] - y) / (2.0 * (1.0 - kr)),
+        ]
+    }
+}
+
+/// `[hue, chroma, max, min]`, what hsv and hsl are both made from. Hue goes from 0.0 to 1.0
+/// round the colour wheel starting at red, and is meaningless when chroma is 0.
+fn hue_chroma([r, g, b]: [f32; 3]) -> [f32; 4] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let chroma = max - min;
+    let hue = if chroma == 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / chroma + 2.0
+    } else {
+        (r - g) / chroma + 4.0
+    };
+    [hue / 6.0, chroma, max, min]
+}
+
+/// `[hue, saturation, value]`
+fn hsv(rgb: [f32; 3]) -> [f32; 3] {
+    let [hue, chroma, max, _] = hue_chroma(rgb);
+    let saturation = if max > 0.0 { chroma / max } else { 0.0 };
+    [hue, saturation, max]
+}
+
+/// `[hue, saturation, lightness]`
+fn hsl(rgb: [f32; 3]) -> [f32; 3] {
+    let [hue, chroma, max, min] = hue_chroma(rgb);
+    let lightness = (max + min) / 2.0;
+    let saturation = if lightness > 0.0 && lightness < 1.0 {
+        chroma / (1.0 - (2.0 * lightness - 1.0).abs())
+    } else {
+        0.0
+    };
+    [hue, saturation, lightness]
+}
+
+/// Which of `bins` bins a value in `0.0..=1.0` goes in.
+fn bin(value: f32, bins: usize) -> usize {
+    ((value * bins as f32) as usize).min(bins - 1)
+}
+
+/// The part of `image` inside `roi` (from 0.0 to 1.0 across and down), or all of it when
+/// there's no roi. Pixels the roi only partly covers are included, and an roi with no area
+/// (or that misses the image) gives an empty image.
+fn region(image: &MyImage, roi: Option<Rect>) -> Cow<'_, MyImage> {
+    let Some(roi) = roi.filter(|_| image.is_loaded()) else {
+        return Cow::Borrowed(image);
+    };
+    // dragging up or to the left gives a rect that is the wrong way round
+    let roi = Rect::from_two_pos(roi.min, roi.max);
+    let (width, height) = (image.width as usize, image.height as usize);
+    let span = |from: f32, to: f32, size: usize| {
+        let (from, to) = (from.clamp(0.0, 1.0), to.clamp(0.0, 1.0));
+        let start = ((from * size as f32) as usize).min(size);
+        (start, ((to * size as f32).ceil() as usize).min(size))
+    };
+    let (x0, x1) = span(roi.min.x, roi.max.x, width);
+    let (y0, y1) = span(roi.min.y, roi.max.y, height);
+    if x0 >= x1 || y0 >= y1 {
+        return Cow::Owned(MyImage::default());
+    }
+    let bytes = (y0..y1)
+        .flat_map(|y| &image.bytes[(y * width + x0) * 4..(y * width + x1) * 4])
+        .copied()
+        .collect();
+    Cow::Owned(MyImage {
+        width: (x1 - x0) as u32,
+        height: (y1 - y0) as u32,
+        bytes,
+        ..Default::default()
+    })
+}
+
+/// Undoes the srgb transfer curve, giving light that adds up.
+fn to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Numbers for one channel, in `0..=255`.
+#[derive(Clone, Copy, Default)]
+pub struct ChannelStats {
+    mean: f32,
+    median: u8,
+    std_dev: f32,
+    min: u8,
+    max: u8,
+    /// percent of pixels at 255
+    clipped: f32,
+    /// percent of pixels at 0
+    crushed: f32,
+}
+
+impl ChannelStats {
+    fn from_counts(counts: &[usize; 256], total: usize) -> ChannelStats {
+        let total_f = total as f32;
+        let mean = counts
+            .iter()
+            .enumerate()
+            .map(|(v, &n)| v as f32 * n as f32)
+            .sum::<f32>()
+            / total_f;
+        let variance = counts
+            .iter()
+            .enumerate()
+            .map(|(v, &n)| (v as f32 - mean).powi(2) * n as f32)
+            .sum::<f32>()
+            / total_f;
+        let mut seen = 0;
+        let median = counts
+            .iter()
+            .position(|&n| {
+                seen += n;
+                seen * 2 >= total
+            })
+            .unwrap_or(0);
+        ChannelStats {
+            mean,
+            median: median as u8,
+            std_dev: variance.sqrt(),
+            min: counts.iter().position(|&n| n > 0).unwrap_or(0) as u8,
+            max: counts.iter().rposition(|&n| n > 0).unwrap_or(0) as u8,
+            clipped: counts[255] as f32 / total_f * 100.0,
+            crushed: counts[0] as f32 / total_f * 100.0,
+        }
+    }
+}
+
+/// Numbers describing a whole image, the other half of what the histogram shows.
+#[derive(Clone)]
+pub struct ImageStats {
+    /// red, green, blue and Rec. 709 luma
+    channels: [ChannelStats; 4],
+    /// between the 1st and 99th percentile of (linear) luminance, so a few hot or dead
+    /// pixels don't count
+    dynamic_range: f32,
+    /// how colourful the image is on average, OkLab's distance from grey
+    chroma: f32,
+}
+
+const STAT_CHANNELS: [&str; 4] = ["R", "G", "B", "luma"];
+
+impl ImageStats {
+    /// `None` for an image with no pixels.
+    pub fn of(image: &MyImage) -> Option<ImageStats> {
+        let total = image.bytes.len() / 4;
+        if total == 0 {
+            return None;
+        }
+        let mut counts = [[0_usize; 256]; 4];
+        let mut luminance = Vec::with_capacity(total);
+        let mut chroma = 0.0;
+        for px in image.bytes.chunks_exact(4) {
+            let (r, g, b) = (px[0], px[1], px[2]);
+            let rgb = [r, g, b].map(|c| c as f32 / 255.0);
+            for (i, c) in [r, g, b].into_iter().enumerate() {
+                counts[i][c as usize] += 1;
+            }
+            counts[3][(luma(rgb) * 255.0).round() as usize] += 1;
+            luminance.push(luma(rgb.map(to_linear)));
+            let ok_lab = oklab::srgb_to_oklab(Rgb { r, g, b });
+            chroma += ok_lab.a.hypot(ok_lab.b);
+        }
+
+        let mut percentile = |p: f32| {
+            *luminance
+                .select_nth_unstable_by(((total - 1) as f32 * p) as usize, f32::total_cmp)
+                .1
+        };
+        // an 8 bit pixel can't get darker than this without being black
+        let floor = to_linear(0.5 / 255.0);
+        let dark = percentile(0.01).max(floor);
+        let bright = percentile(0.99).max(floor);
+        Some(ImageStats {
+            channels: counts.map(|c| ChannelStats::from_counts(&c, total)),
+            dynamic_range: (bright / dark).log2(),
+            chroma: chroma / total as f32,
+        })
+    }
+
+    /// One row of the table, a number for each channel.
+    fn rows(&self) -> Vec<(&str, [String; 4])> {
+        let row = |f: &dyn Fn(&ChannelStats) -> String| self.channels.map(|c| f(&c));
+        vec![
+            ("mean", row(&|c| format!("{:.1}", c.mean))),
+            ("median", row(&|c| c.median.to_string())),
+            ("std dev", row(&|c| format!("{:.1}", c.std_dev))),
+            ("min", row(&|c| c.min.to_string())),
+            ("max", row(&|c| c.max.to_string())),
+            ("clipped", row(&|c| format!("{:.2}%", c.clipped))),
+            ("crushed", row(&|c| format!("{:.2}%", c.crushed))),
+        ]
+    }
+
+    fn render(&self, ui: &mut Ui) {
+        egui::Grid::new("image stats").striped(true).show(ui, |ui| {
+            ui.label("");
+            for name in STAT_CHANNELS {
+                ui.label(RichText::new(name).strong());
+            }
+            ui.end_row();
+            for (name, values) in self.rows() {
+                ui.label(name);
+                for value in values {
+                    ui.label(RichText::new(value).monospace());
+                }
+                ui.end_row();
+            }
+        });
+        ui.label(format!("dynamic range: {:.1} stops", self.dynamic_range));
+        ui.label(format!("mean chroma (OkLab): {:.3}", self.chroma));
+    }
+}
+
+impl std::fmt::Display for ImageStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:<8}", "")?;
+        for name in STAT_CHANNELS {
+            write!(f, "{name:>8}")?;
+        }
+        writeln!(f)?;
+        for (name, values) in self.rows() {
+            write!(f, "{name:<8}")?;
+            for value in values {
+                write!(f, "{value:>8}")?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "dynamic range: {:.1} stops", self.dynamic_range)?;
+        write!(f, "mean chroma (OkLab): {:.3}", self.chroma)
+    }
+}
+
+/// What the histogram is of.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Default, Debug)]
+pub enum HistogramSource {
+    /// the live preview
+    #[default]
+    Live,
+    /// a photo, by id
+    Photo(String),
+}
+
+/// How two histograms are shown when comparing.
+#[derive(serde::Deserialize, serde::Serialize, EnumIter, PartialEq, Eq, Clone, Copy, Default)]
+pub enum CompareView {
+    /// on the same plots, the second one dashed
+    #[default]
+    Overlay,
+    SideBySide,
+}
+
+impl CompareView {
+    fn name(&self) -> &str {
+        match self {
+            CompareView::Overlay => "overlay",
+            CompareView::SideBySide => "side by side",
+        }
+    }
+}
+
+/// Photos are analysed at up to this many pixels across (or down), like the live preview is.
+const ANALYSIS_SIZE: u32 = 1024;
+
+/// Every nth pixel of `image`, so it's no more than `max` across or down. Pixels are picked
+/// rather than averaged, so clipping still shows up.
+fn sample(image: &MyImage, max: u32) -> MyImage {
+    let step = image.width.max(image.height).div_ceil(max).max(1) as usize;
+    if step == 1 {
+        return image.clone();
+    }
+    let (width, height) = (image.width as usize, image.height as usize);
+    let bytes = (0..height)
+        .step_by(step)
+        .flat_map(|y| {
+            (0..width)
+                .step_by(step)
+                .flat_map(move |x| &image.bytes[(y * width + x) * 4..][..4])
+        })
+        .copied()
+        .collect();
+    MyImage {
+        width: width.div_ceil(step) as u32,
+        height: height.div_ceil(step) as u32,
+        bytes,
+        ..Default::default()
+    }
+}
 
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)]
 pub struct HistogramData {
     type_: HistogramType,
+    ycbcr: YCbCrMatrix,
+    bins: usize,
+    /// plot the counts on a log scale, so small ones don't disappear next to a big spike
+    log_scale: bool,
+    /// plot how many pixels are at or below each bin, rather than in it
+    cumulative: bool,
+    /// a plot per channel rather than all of them on top of each other
+    split: bool,
+    /// the part of the image (from 0.0 to 1.0 across and down) the histogram and statistics
+    /// cover, all of it when `None`
+    roi: Option<Rect>,
+    source: HistogramSource,
+    /// a second image to compare against
+    compare: Option<HistogramSource>,
+    compare_view: CompareView,
+    /// photos being analysed, developed and sampled down, keyed by id with the recipe
+    /// they were developed with
+    #[serde(skip)]
+    analysed: HashMap<String, (Vec<FilterStep>, MyImage)>,
+    /// what was worked out for those photos, so it's only redone when something changes.
+    /// The live preview changes every frame, so it's never kept
     #[serde(skip)]
-    data: [[f32; 3]; 64],
+    results: Vec<(AnalysisKey, Analysis)>,
 }
 
 impl Default for HistogramData {
     fn default() -> Self {
         HistogramData {
             type_: HistogramType::Rgb,
-            data: [[1_f32; 3]; 64],
+            ycbcr: YCbCrMatrix::default(),
+            bins: 64,
+            log_scale: false,
+            cumulative: false,
+            split: false,
+            roi: None,
+            source: HistogramSource::Live,
+            compare: None,
+            compare_view: CompareView::default(),
+            analysed: HashMap::new(),
+            results: vec![],
         }
     }
+}
+
+/// Everything an [`Analysis`] of a photo depends on.
+#[derive(PartialEq)]
+struct AnalysisKey {
+    id: String,
+    recipe: Vec<FilterStep>,
+    roi: Option<Rect>,
+    type_: HistogramType,
+    ycbcr: YCbCrMatrix,
+    bins: usize,
+    log_scale: bool,
+    cumulative: bool,
+}
 
+/// The histogram and statistics of one image (or the metered part of it).
+#[derive(Clone)]
+struct Analysis {
+    size: [u32; 2],
+    data: Vec<[f32; 3]>,
+    stats: Option<ImageStats>,
 }
+
+/// One channel as a line across the plot.
+fn line(data: &[[f32; 3]], channel: usize) -> Line {
+    Line::new(
+        data.iter()
+            .enumerate()
+            .map(|(x, d)| [x as f64, d[channel] as f64])
+            .collect::<Vec<[f64; 2]>>(),
+    )
+}
+
 impl HistogramData {
-    fn update(&mut self, image: &MyImage) {
-        self.data = [[0_f32;3];64];
-        let mut max: f32 = 0.0;
-        let mut max_b: f32 = 0.0;
-        for i in 0..(image.bytes.len() / 4) {
-            let r = image.bytes[i * 4];
-            let g = image.bytes[i * 4 + 1];
-            let b = image.bytes[i * 4 + 2];
+    /// The photo the histogram is of, for showing instead of the live preview. `None` when
+    /// it is of the live preview, or the photo hasn't been developed yet.
+    pub fn photo(&self) -> Option<&MyImage> {
+        match &self.source {
+            HistogramSource::Live => None,
+            HistogramSource::Photo(id) => self.analysed.get(id).map(|(_, img)| img),
+        }
+    }
+
+    /// What an analysis of `source` would depend on right now, `None` for the live preview
+    /// and photos that haven't been developed yet.
+    fn key(&self, source: &HistogramSource) -> Option<AnalysisKey> {
+        let HistogramSource::Photo(id) = source else {
+            return None;
+        };
+        let (recipe, _) = self.analysed.get(id)?;
+        Some(AnalysisKey {
+            id: id.clone(),
+            recipe: recipe.clone(),
+            roi: self.roi,
+            type_: self.type_,
+            ycbcr: self.ycbcr,
+            bins: self.bins,
+            log_scale: self.log_scale,
+            cumulative: self.cumulative,
+        })
+    }
+
+    fn analyse(&self, image: &MyImage) -> Analysis {
+        let image = region(image, self.roi);
+        Analysis {
+            size: [image.width, image.height],
+            data: self.compute(&image),
+            stats: ImageStats::of(&image),
+        }
+    }
+
+    /// The histogram of `image`, `bins` long, scaled so the tallest bin is a bit under 1.
+    fn compute(&self, image: &MyImage) -> Vec<[f32; 3]> {
+        let bins = self.bins;
+        let bin = |value: f32| bin(value, bins);
+        let mut data = vec![[0_f32; 3]; bins];
+        for px in image.bytes.chunks_exact(4) {
+            let (r, g, b) = (px[0], px[1], px[2]);
+            let rgb = [r, g, b].map(|c| c as f32 / 255.0);
             match self.type_ {
                 HistogramType::Rgb => {
-                    self.data[r as usize / 4][0] += 1.0;
-                    self.data[g as usize / 4][1] += 1.0;
-                    self.data[b as usize / 4][2] += 1.0;
+                    for (i, c) in rgb.into_iter().enumerate() {
+                        data[bin(c)][i] += 1.0;
+                    }
                 },
                 HistogramType::OkLab => {
                     let ok_lab = oklab::srgb_to_oklab(Rgb{r,g,b});
-                    self.data[((ok_lab.a + 0.5)* 64.0) as usize][0] += 1.0;
-                    self.data[((ok_lab.b + 0.5)* 64.0) as usize][1] += 1.0;
-                    self.data[(ok_lab.l * 63.0) as usize][2] += 1.0;
-                    max_b = max_b.max(self.data[(ok_lab.l * 63.0) as usize][2]);
+                    data[bin(ok_lab.a + 0.5)][0] += 1.0;
+                    data[bin(ok_lab.b + 0.5)][1] += 1.0;
+                    data[bin(ok_lab.l)][2] += 1.0;
+                }
+                HistogramType::Hsv | HistogramType::Hsl => {
+                    let [hue, saturation, brightness] = if self.type_ == HistogramType::Hsv {
+                        hsv(rgb)
+                    } else {
+                        hsl(rgb)
+                    };
+                    // greys have no hue, counting them would pile up on red
+                    if hue_chroma(rgb)[1] > 0.0 {
+                        data[bin(hue)][0] += 1.0;
+                    }
+                    data[bin(saturation)][1] += 1.0;
+                    data[bin(brightness)][2] += 1.0;
+                }
+                HistogramType::YCbCr => {
+                    for (i, c) in self.ycbcr.ycbcr(rgb).into_iter().enumerate() {
+                        data[bin(c)][i] += 1.0;
+                    }
+                }
+                HistogramType::Luminance => {
+                    data[bin(luma(rgb))][0] += 1.0;
                 }
             }
         }
 
-        
+        if self.cumulative {
+            for i in 1..bins {
+                let previous = data[i - 1];
+                for (p, previous) in data[i].iter_mut().zip(previous) {
+                    *p += previous;
+                }
+            }
+        }
+        if self.log_scale {
+            for d in &mut data {
+                for p in d {
+                    *p = p.ln_1p();
+                }
+            }
+        }
 
-        for d in self.data {
-            for p in d {
-                max = max.max(p);
+        let groups = self.type_.scale_groups();
+        let mut max = [0_f32; 3];
+        for d in &data {
+            for i in 0..3 {
+                max[groups[i]] = max[groups[i]].max(d[i]);
             }
         }
-        for d in &mut self.data {
-            for (i,p) in d.iter_mut().enumerate() {
-                if self.type_ == HistogramType::OkLab && i == 2 {
-                    *p /= max_b * 1.1;
-                }else {
-                *p /= max * 1.1;
+        for d in &mut data {
+            for i in 0..3 {
+                if max[groups[i]] > 0.0 {
+                    d[i] /= max[groups[i]] * 1.1;
+                }
             }
+        }
+        data
+    }
+
+    /// Every line to plot for `data`, with the channel it shows so they can be split up.
+    fn lines(&self, data: &[[f32; 3]]) -> Vec<(usize, Line)> {
+        let bins = data.len();
+        let mut lines = vec![];
+
+        match self.type_ {
+            HistogramType::Rgb => {
+                for i in 0..3 {
+                    lines.push((
+                        i,
+                        line(data, i).stroke(Stroke::new(
+                            0.8_f32,
+                            [Color32::RED, Color32::DARK_GREEN, Color32::BLUE][i],
+                        )),
+                    ));
+                }
+            }
+            HistogramType::OkLab => {
+                for index in 0..5 {
+                    let i = index % 3;
+                    lines.push((
+                        i,
+                        Line::new(
+                            data.iter()
+                                .enumerate()
+                                .filter(|x| match index {
+                                    0..=1 => x.0 <= bins / 2,
+                                    3..=4 => x.0 >= bins / 2,
+                                    _ => true,
+                                })
+                                .map(|x| [x.0 as f64, x.1[i] as f64])
+                                .collect::<Vec<[f64; 2]>>(),
+                        )
+                        .stroke(Stroke::new(
+                            0.8_f32,
+                            [
+                                Color32::GREEN,
+                                Color32::BLUE,
+                                Color32::DARK_GRAY,
+                                Color32::RED,
+                                Color32::YELLOW,
+                            ][index],
+                        )),
+                    ));
+                }
+            }
+            HistogramType::Hsv | HistogramType::Hsl => {
+                let brightness = if self.type_ == HistogramType::Hsv {
+                    "value"
+                } else {
+                    "lightness"
+                };
+                lines.push((
+                    1,
+                    line(data, 1)
+                        .color(Color32::from_rgb(160, 60, 200))
+                        .name("saturation"),
+                ));
+                lines.push((2, line(data, 2).color(Color32::DARK_GRAY).name(brightness)));
+            }
+            HistogramType::YCbCr => {
+                for (i, (name, color)) in [
+                    ("Y", Color32::DARK_GRAY),
+                    ("Cb", Color32::BLUE),
+                    ("Cr", Color32::RED),
+                ]
+                .into_iter()
+                .enumerate()
+                {
+                    lines.push((i, line(data, i).color(color).name(name)));
+                }
+            }
+            HistogramType::Luminance => {
+                lines.push((
+                    0,
+                    line(data, 0)
+                        .color(Color32::DARK_GRAY)
+                        .name("Rec. 709 luma"),
+                ));
             }
         }
+        lines
+    }
+
+    fn render_options(&mut self, ui: &mut Ui) {
+        ui.add(egui::Slider::new(&mut self.bins, BINS).text("bins"));
+        ui.horizontal_wrapped(|ui| {
+            ui.checkbox(&mut self.log_scale, "log scale");
+            ui.checkbox(&mut self.cumulative, "cumulative");
+            ui.selectable_value(&mut self.split, false, "overlay");
+            ui.selectable_value(&mut self.split, true, "split");
+        });
+    }
 
+    /// The hue histogram wrapped round a circle, every bin a spoke in its own colour. The hue
+    /// of `compare` is drawn as a dashed outline on top.
+    fn render_hue_wheel(
+        &self,
+        ui: &mut Ui,
+        id: impl std::hash::Hash,
+        data: &[[f32; 3]],
+        compare: Option<&[[f32; 3]]>,
+    ) {
+        let size = ui.available_width().min(200.0);
+        // spokes start from a ring so the small ones can still be seen
+        let inner = 0.3;
+        let outline = |data: &[[f32; 3]]| {
+            let mut points = data
+                .iter()
+                .enumerate()
+                .map(|(i, d)| {
+                    let (sin, cos) = ((i as f64 + 0.5) / data.len() as f64 * TAU).sin_cos();
+                    let r = inner + d[0] as f64 * (1.0 - inner);
+                    [r * cos, r * sin]
+                })
+                .collect::<Vec<[f64; 2]>>();
+            if let Some(&first) = points.first() {
+                points.push(first);
+            }
+            Line::new(points).color(Color32::DARK_GRAY).width(0.8_f32)
+        };
+        egui_plot::Plot::new(id)
+            .allow_scroll(false)
+            .allow_zoom(false)
+            .allow_drag(false)
+            .show_axes(false)
+            .show_grid(false)
+            .data_aspect(1.0)
+            .width(size)
+            .height(size)
+            .include_x(-1.0)
+            .include_x(1.0)
+            .include_y(-1.0)
+            .include_y(1.0)
+            .show(ui, |plot_ui| {
+                for (i, d) in data.iter().enumerate() {
+                    let hue = (i as f64 + 0.5) / data.len() as f64;
+                    let (sin, cos) = (hue * TAU).sin_cos();
+                    let r = inner + d[0] as f64 * (1.0 - inner);
+                    plot_ui.line(
+                        Line::new(vec![[inner * cos, inner * sin], [r * cos, r * sin]])
+                            .color(Color32::from(Hsva::new(hue as f32, 1.0, 1.0, 1.0)))
+                            .width(3.0_f32),
+                    );
+                }
+                plot_ui.line(outline(data));
+                if let Some(compare) = compare {
+                    plot_ui.line(outline(compare).style(LineStyle::dashed_loose()));
+                }
+            });
+    }
+
+    /// The plots for `data`, with `compare` dashed over the top of them. `key` keeps the
+    /// plots apart when there's more than one set on screen.
+    fn render_curves(
+        &self,
+        ui: &mut Ui,
+        key: usize,
+        data: &[[f32; 3]],
+        compare: Option<&[[f32; 3]]>,
+    ) {
+        if matches!(self.type_, HistogramType::Hsv | HistogramType::Hsl) {
+            self.render_hue_wheel(ui, ("hue histogram", key), data, compare);
+        }
+        let bins = data.len();
+        let mut lines = self.lines(data);
+        if let Some(compare) = compare {
+            lines.extend(
+                self.lines(compare)
+                    .into_iter()
+                    .map(|(channel, line)| (channel, line.style(LineStyle::dashed_loose()))),
+            );
+        }
+        if self.split {
+            for channel in 0..3 {
+                let these;
+                (these, lines) = lines.into_iter().partition(|(c, _)| *c == channel);
+                if !these.is_empty() {
+                    plot(ui, ("histogram", key, channel), bins, Some(80.0), these);
+                }
+            }
+        } else {
+            plot(ui, ("histogram", key), bins, None, lines);
+        }
     }
 }
 
 impl MyApp {
+    /// Gets a photo being analysed ready in `histogram.analysed`. `Ok(false)` while it's
+    /// still loading.
+    fn prepare_histogram_source(&mut self, source: &HistogramSource) -> anyhow::Result<bool> {
+        let HistogramSource::Photo(id) = source else {
+            return Ok(true);
+        };
+        let index = self
+            .photos
+            .iter()
+            .position(|p| &p.id == id)
+            .ok_or(anyhow!("photo {id} is gone"))?;
+        if self
+            .histogram
+            .analysed
+            .get(id)
+            .is_some_and(|(recipe, _)| recipe == &self.photos[index].recipe)
+        {
+            return Ok(true);
+        }
+        let was_loaded = self.photos[index].is_loaded();
+        if !self.load_photo(index)? {
+            return Ok(false);
+        }
+        let developed = self.develop(index)?;
+        // only loaded to be analysed, like thumbnails
+        if !was_loaded {
+            self.photos[index].bytes = vec![];
+        }
+        self.histogram.analysed.insert(
+            id.clone(),
+            (developed.recipe.clone(), sample(&developed, ANALYSIS_SIZE)),
+        );
+        Ok(true)
+    }
+
+    fn histogram_image(&self, source: &HistogramSource) -> Option<&MyImage> {
+        match source {
+            HistogramSource::Live => Some(&self.photo),
+            HistogramSource::Photo(id) => self.histogram.analysed.get(id).map(|(_, img)| img),
+        }
+    }
+
+    fn histogram_source_name(&self, source: &HistogramSource) -> String {
+        match source {
+            HistogramSource::Live => "live preview".to_owned(),
+            HistogramSource::Photo(id) => self
+                .photos
+                .iter()
+                .find(|p| &p.id == id)
+                .map_or("deleted photo".to_owned(), |p| p.name.clone()),
+        }
+    }
+
+    fn render_histogram_sources(&mut self, ui: &mut Ui) {
+        let choices = std::iter::once(HistogramSource::Live)
+            .chain(
+                self.photos
+                    .iter()
+                    .map(|p| HistogramSource::Photo(p.id.clone())),
+            )
+            .map(|s| {
+                let name = self.histogram_source_name(&s);
+                (s, name)
+            })
+            .collect::<Vec<(HistogramSource, String)>>();
+
+        let selected = self.histogram_source_name(&self.histogram.source);
+        egui::ComboBox::from_label("histogram of")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                for (source, name) in &choices {
+                    ui.selectable_value(&mut self.histogram.source, source.clone(), name);
+                }
+            });
+
+        let selected = match &self.histogram.compare {
+            Some(source) => self.histogram_source_name(source),
+            None => "nothing".to_owned(),
+        };
+        egui::ComboBox::from_label("compared with")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.histogram.compare, None, "nothing");
+                for (source, name) in &choices {
+                    ui.selectable_value(&mut self.histogram.compare, Some(source.clone()), name);
+                }
+            });
+        if self.histogram.compare.is_some() {
+            ui.horizontal_wrapped(|ui| {
+                for i in CompareView::iter() {
+                    ui.selectable_value(&mut self.histogram.compare_view, i, i.name());
+                }
+            });
+        }
+    }
+
     pub fn render_histogram(&mut self, ui: &mut Ui) {
         ui.horizontal_wrapped(|ui| {
             for i in HistogramType::iter() {
@@ -100,64 +864,532 @@ impl MyApp {
                 });
             }
         });
-        self.histogram.update(&self.photo);
-        egui_plot::Plot::new("histogram")
-            .allow_scroll(false)
-            .allow_zoom(false)
-            .allow_drag(false)
-            .include_y(1.0)
-            .include_x(64)
-            .show(ui, |plot_ui| {
-                let mut lines = vec![];
-
-                match self.histogram.type_ {
-                    HistogramType::Rgb => {
-                        for i in 0..3 {
-                            lines.push(
-                                Line::new(
-                                    self.histogram
-                                        .data
-                                        .iter()
-                                        .enumerate()
-                                        .map(|x| [x.0 as f64, x.1[i] as f64])
-                                        .collect::<Vec<[f64; 2]>>(),
-                                )
-                                .stroke(Stroke::new(0.8, [Color32::RED, Color32::DARK_GREEN, Color32::BLUE][i]))
-                                ,
-                            );
-                        }
-                    }
-                    HistogramType::OkLab => {
-                        for index in 0..5 {
-                            let i = index % 3;
-                            lines.push(
-                                Line::new(
-                                    self.histogram
-                                        .data
-                                        .iter()
-                                        .enumerate()
-                                        .filter(|x| 
-                                            match index {
-                                                0..=1 => x.0 <= 32,
-                                                3..=4 => x.0 >= 32,
-                                                _ => true,
-                                            }
-                                        )
-                                        .map(|x| [x.0 as f64, x.1[i] as f64])
-                                        .collect::<Vec<[f64; 2]>>(),
-                                )
-                                .stroke(
-                                    Stroke::new(0.8, [Color32::GREEN, Color32::BLUE, Color32::DARK_GRAY,Color32::RED,Color32::YELLOW][index])
-                                )
-                                ,
-                            );
-                        }
+        if self.histogram.type_ == HistogramType::YCbCr {
+            ui.horizontal_wrapped(|ui| {
+                for i in YCbCrMatrix::iter() {
+                    ui.selectable_value(&mut self.histogram.ycbcr, i, i.name());
+                }
+            });
+        }
+        egui::CollapsingHeader::new("options")
+            .id_salt("histogram options")
+            .show(ui, |ui| self.histogram.render_options(ui));
+        self.render_histogram_sources(ui);
+        self.histogram.bins = self.histogram.bins.clamp(*BINS.start(), *BINS.end());
+
+        // a photo that has been deleted can't be analysed, go back to something that can
+        let mut sources = vec![self.histogram.source.clone()];
+        if let Err(e) = self.prepare_histogram_source(&sources[0]) {
+            log::warn!("can't show a histogram of that: {e}");
+            self.histogram.source = HistogramSource::Live;
+            sources[0] = HistogramSource::Live;
+        }
+        if let Some(compare) = self.histogram.compare.clone() {
+            match self.prepare_histogram_source(&compare) {
+                Ok(_) => sources.push(compare),
+                Err(e) => {
+                    log::warn!("can't compare with that: {e}");
+                    self.histogram.compare = None;
+                }
+            }
+        }
+        self.histogram
+            .analysed
+            .retain(|id, _| sources.contains(&HistogramSource::Photo(id.clone())));
+        let keys = sources
+            .iter()
+            .map(|s| self.histogram.key(s))
+            .collect::<Vec<Option<AnalysisKey>>>();
+        self.histogram
+            .results
+            .retain(|(key, _)| keys.iter().any(|k| k.as_ref() == Some(key)));
+
+        let mut analyses = vec![];
+        for (source, key) in sources.iter().zip(keys) {
+            let name = self.histogram_source_name(source);
+            let cached = self
+                .histogram
+                .results
+                .iter()
+                .find(|(k, _)| Some(k) == key.as_ref());
+            let analysis = match (cached, self.histogram_image(source)) {
+                (Some((_, analysis)), _) => analysis.clone(),
+                (None, Some(image)) => {
+                    let analysis = self.histogram.analyse(image);
+                    if let Some(key) = key {
+                        self.histogram.results.push((key, analysis.clone()));
                     }
+                    analysis
                 }
+                (None, None) => {
+                    ui.label(format!("loading {name}"));
+                    ui.ctx().request_repaint();
+                    return;
+                }
+            };
+            analyses.push((name, analysis));
+        }
+
+        match self.histogram.roi {
+            Some(_) => {
+                ui.horizontal_wrapped(|ui| {
+                    let [width, height] = analyses[0].1.size;
+                    ui.label(RichText::new(format!("metering {width}x{height} pixels")).small());
+                    if ui.small_button("whole image").clicked() {
+                        self.histogram.roi = None;
+                    }
+                });
+            }
+            None => {
+                ui.label(
+                    RichText::new("drag on the image to meter part of it")
+                        .small()
+                        .weak(),
+                );
+            }
+        }
+
+        let data = analyses
+            .iter()
+            .map(|(_, a)| &a.data)
+            .collect::<Vec<&Vec<[f32; 3]>>>();
+        match (&data[..], self.histogram.compare_view) {
+            ([first, second], CompareView::SideBySide) => {
+                ui.columns(2, |columns| {
+                    for (key, (ui, data)) in columns.iter_mut().zip([first, second]).enumerate() {
+                        ui.label(RichText::new(&analyses[key].0).small());
+                        self.histogram.render_curves(ui, key, data, None);
+                    }
+                });
+            }
+            ([first, second], CompareView::Overlay) => {
+                ui.label(
+                    RichText::new(format!(
+                        "solid: {}, dashed: {}",
+                        analyses[0].0, analyses[1].0
+                    ))
+                    .small(),
+                );
+                self.histogram.render_curves(ui, 0, first, Some(second));
+            }
+            _ => self.histogram.render_curves(ui, 0, data[0], None),
+        }
 
-                for l in lines {
-                    plot_ui.line(l);
+        egui::CollapsingHeader::new("statistics")
+            .id_salt("image stats")
+            .show(ui, |ui| {
+                for (key, (name, analysis)) in analyses.iter().enumerate() {
+                    if analyses.len() > 1 {
+                        ui.label(RichText::new(name).strong());
+                    }
+                    ui.push_id(key, |ui| match &analysis.stats {
+                        Some(stats) => {
+                            stats.render(ui);
+                            if ui.small_button("copy as text").clicked() {
+                                ui.ctx().copy_text(stats.to_string());
+                            }
+                        }
+                        None => {
+                            ui.label("no image");
+                        }
+                    });
                 }
             });
     }
+
+    /// Dragging over the image (`image_rect` on screen) picks the region the histogram covers,
+    /// which is drawn over it. A tap goes back to the whole image.
+    pub fn render_roi(&mut self, ui: &mut Ui, image_rect: Rect) {
+        let response = ui.interact(image_rect, ui.id().with("roi"), Sense::click_and_drag());
+        let to_image = |pos: Pos2| {
+            let t = (pos - image_rect.min) / image_rect.size();
+            pos2(t.x.clamp(0.0, 1.0), t.y.clamp(0.0, 1.0))
+        };
+        let start_id = ui.id().with("roi start");
+        if response.drag_started() {
+            // drags only count as started once the pointer has moved a bit, start where it went down
+            if let Some(pos) = ui.input(|i| i.pointer.press_origin()) {
+                ui.data_mut(|d| d.insert_temp(start_id, to_image(pos)));
+            }
+        }
+        if response.dragged() {
+            if let (Some(start), Some(pos)) = (
+                ui.data(|d| d.get_temp::<Pos2>(start_id)),
+                response.interact_pointer_pos(),
+            ) {
+                self.histogram.roi = Some(Rect::from_two_pos(start, to_image(pos)));
+            }
+        }
+        let too_small = self
+            .histogram
+            .roi
+            .is_some_and(|roi| roi.width() < 0.01 || roi.height() < 0.01);
+        if response.clicked() || (response.drag_stopped() && too_small) {
+            self.histogram.roi = None;
+        }
+
+        if let Some(roi) = self.histogram.roi {
+            let rect = Rect::from_min_max(
+                image_rect.lerp_inside(roi.min.to_vec2()),
+                image_rect.lerp_inside(roi.max.to_vec2()),
+            );
+            let painter = ui.painter_at(image_rect);
+            // darken everything outside the region
+            let shade = Color32::from_black_alpha(120);
+            for outside in [
+                Rect::from_min_max(image_rect.min, pos2(image_rect.max.x, rect.min.y)),
+                Rect::from_min_max(pos2(image_rect.min.x, rect.max.y), image_rect.max),
+                Rect::from_min_max(
+                    pos2(image_rect.min.x, rect.min.y),
+                    pos2(rect.min.x, rect.max.y),
+                ),
+                Rect::from_min_max(
+                    pos2(rect.max.x, rect.min.y),
+                    pos2(image_rect.max.x, rect.max.y),
+                ),
+            ] {
+                painter.rect_filled(outside, 0.0, shade);
+            }
+            painter.rect_stroke(rect, 0.0, Stroke::new(1.0_f32, Color32::YELLOW));
+        }
+    }
+}
+
+/// Shows `lines` in a plot `bins` wide, `height` tall or as tall as there is room for.
+fn plot(
+    ui: &mut Ui,
+    id: impl std::hash::Hash,
+    bins: usize,
+    height: Option<f32>,
+    lines: Vec<(usize, Line)>,
+) {
+    let mut plot = egui_plot::Plot::new(id)
+        .allow_scroll(false)
+        .allow_zoom(false)
+        .allow_drag(false)
+        .include_y(1.0)
+        .include_x(bins as f64)
+        .legend(Legend::default());
+    if let Some(height) = height {
+        plot = plot.height(height);
+    }
+    plot.show(ui, |plot_ui| {
+        for (_, l) in lines {
+            plot_ui.line(l);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3]) {
+        assert!(
+            actual
+                .iter()
+                .zip(expected)
+                .all(|(a, e)| (a - e).abs() < 1e-4),
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn hsv_and_hsl_of_the_primaries() {
+        for (rgb, hue) in [
+            ([1.0, 0.0, 0.0], 0.0),
+            ([0.0, 1.0, 0.0], 1.0 / 3.0),
+            ([0.0, 0.0, 1.0], 2.0 / 3.0),
+        ] {
+            assert_close(hsv(rgb), [hue, 1.0, 1.0]);
+            assert_close(hsl(rgb), [hue, 1.0, 0.5]);
+        }
+        assert_close(hsv([0.5, 0.25, 0.0]), [1.0 / 12.0, 1.0, 0.5]);
+        assert_close(hsl([0.5, 0.25, 0.0]), [1.0 / 12.0, 1.0, 0.25]);
+    }
+
+    #[test]
+    fn greys_have_no_hue_or_saturation() {
+        for v in [0.0, 0.5, 1.0] {
+            assert_eq!(hue_chroma([v; 3]), [0.0, 0.0, v, v]);
+            assert_close(hsv([v; 3]), [0.0, 0.0, v]);
+            assert_close(hsl([v; 3]), [0.0, 0.0, v]);
+        }
+    }
+
+    #[test]
+    fn hue_wraps_round_at_red() {
+        // a little towards yellow and a little towards magenta end up at opposite ends
+        assert_close(hsv([1.0, 0.2, 0.0]), [0.2 / 6.0, 1.0, 1.0]);
+        assert_close(hsv([1.0, 0.0, 0.2]), [1.0 - 0.2 / 6.0, 1.0, 1.0]);
+        let [hue, ..] = hue_chroma([1.0, 0.0, 0.2]);
+        assert!((0.0..1.0).contains(&hue));
+    }
+
+    #[test]
+    fn ycbcr_matches_the_standards() {
+        for matrix in YCbCrMatrix::iter() {
+            assert_close(matrix.ycbcr([0.0; 3]), [0.0, 0.5, 0.5]);
+            assert_close(matrix.ycbcr([1.0; 3]), [1.0, 0.5, 0.5]);
+            assert_close(matrix.ycbcr([0.5; 3]), [0.5, 0.5, 0.5]);
+            // the most saturated colours reach the edges
+            assert!((matrix.ycbcr([1.0, 0.0, 0.0])[2] - 1.0).abs() < 1e-4);
+            assert!((matrix.ycbcr([0.0, 0.0, 1.0])[1] - 1.0).abs() < 1e-4);
+        }
+        let bt601 = YCbCrMatrix::Bt601;
+        assert_close(bt601.ycbcr([1.0, 0.0, 0.0]), [0.299, 0.331264, 1.0]);
+        assert_close(bt601.ycbcr([0.0, 1.0, 0.0]), [0.587, 0.168736, 0.081313]);
+        assert_close(bt601.ycbcr([0.0, 0.0, 1.0]), [0.114, 1.0, 0.418688]);
+        let bt709 = YCbCrMatrix::Bt709;
+        assert_close(bt709.ycbcr([1.0, 0.0, 0.0]), [0.2126, 0.385428, 1.0]);
+        assert_close(bt709.ycbcr([0.0, 1.0, 0.0]), [0.7152, 0.114572, 0.045847]);
+        assert_close(bt709.ycbcr([0.0, 0.0, 1.0]), [0.0722, 1.0, 0.454153]);
+    }
+
+    /// A one pixel high image.
+    fn image(pixels: &[[u8; 3]]) -> MyImage {
+        MyImage {
+            width: pixels.len() as u32,
+            height: 1,
+            bytes: pixels
+                .iter()
+                .flat_map(|&[r, g, b]| [r, g, b, 255])
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn histogram(type_: HistogramType) -> HistogramData {
+        HistogramData {
+            type_,
+            bins: 16,
+            ..Default::default()
+        }
+    }
+
+    const PIXELS: [[u8; 3]; 4] = [[0, 0, 0], [255, 255, 255], [128, 64, 16], [128, 64, 16]];
+
+    #[test]
+    fn pixels_land_in_their_bins() {
+        let data = histogram(HistogramType::Rgb).compute(&image(&PIXELS));
+        assert_eq!(data.len(), 16);
+        // the tallest bin comes out a bit under 1
+        let full = 1.0 / 1.1;
+        for (i, d) in data.iter().enumerate() {
+            let expected = [
+                [0, 8, 15].contains(&i),
+                [0, 4, 15].contains(&i),
+                [0, 1, 15].contains(&i),
+            ]
+            .map(|filled| if filled { full } else { 0.0 });
+            let expected = match i {
+                0 | 15 => expected.map(|e| e / 2.0),
+                _ => expected,
+            };
+            assert_eq!(*d, expected, "bin {i}");
+        }
+
+        let data = histogram(HistogramType::Luminance).compute(&image(&PIXELS));
+        let filled: Vec<usize> = (0..16).filter(|&i| data[i][0] > 0.0).collect();
+        assert_eq!(
+            filled,
+            [0, bin(luma([128.0, 64.0, 16.0].map(|c| c / 255.0)), 16), 15]
+        );
+        assert!(histogram(HistogramType::Rgb)
+            .compute(&image(&[]))
+            .iter()
+            .all(|d| *d == [0.0; 3]));
+    }
+
+    #[test]
+    fn cumulative_histograms_only_go_up() {
+        for type_ in HistogramType::iter() {
+            let data = HistogramData {
+                cumulative: true,
+                ..histogram(type_)
+            }
+            .compute(&image(&PIXELS));
+            for pair in data.windows(2) {
+                assert!((0..3).all(|i| pair[0][i] <= pair[1][i]));
+            }
+        }
+        let data = HistogramData {
+            cumulative: true,
+            ..histogram(HistogramType::Rgb)
+        }
+        .compute(&image(&PIXELS));
+        // every pixel is at or below the last bin, which makes it the tallest
+        assert_eq!(data[15], [1.0 / 1.1; 3]);
+        assert_eq!(data[0], [0.25 / 1.1; 3]);
+    }
+
+    #[test]
+    fn log_scale_leaves_empty_bins_empty() {
+        let data = HistogramData {
+            log_scale: true,
+            ..histogram(HistogramType::Rgb)
+        }
+        .compute(&image(&PIXELS));
+        assert_eq!(data[3], [0.0; 3]);
+        // one pixel against two
+        assert!((data[0][0] / data[8][0] - 2_f32.ln() / 3_f32.ln()).abs() < 1e-6);
+    }
+
+    fn stats(pixels: &[[u8; 3]]) -> ImageStats {
+        ImageStats::of(&image(pixels)).unwrap()
+    }
+
+    #[test]
+    fn stats_of_flat_images() {
+        for (v, clipped, crushed) in [(0, 0.0, 100.0), (255, 100.0, 0.0)] {
+            let flat = stats(&[[v; 3]; 6]);
+            for c in flat.channels {
+                assert_eq!(c.mean, v as f32);
+                assert_eq!((c.median, c.min, c.max), (v, v, v));
+                assert_eq!(c.std_dev, 0.0);
+                assert_eq!((c.clipped, c.crushed), (clipped, crushed));
+            }
+            assert_eq!(flat.dynamic_range, 0.0);
+            assert!(flat.chroma.abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn stats_of_half_black_half_white() {
+        let half = stats(&[[0; 3], [255; 3], [0; 3], [255; 3]]);
+        for c in half.channels {
+            assert_eq!(c.mean, 127.5);
+            // the first value with half the pixels at or below it
+            assert_eq!(c.median, 0);
+            assert_eq!((c.min, c.max), (0, 255));
+            assert_eq!(c.std_dev, 127.5);
+            assert_eq!((c.clipped, c.crushed), (50.0, 50.0));
+        }
+        // from the darkest a pixel can be without being black, up to white
+        assert_eq!(half.dynamic_range, (1.0 / to_linear(0.5 / 255.0)).log2());
+
+        let red = stats(&[[0; 3], [0; 3], [0; 3], [255; 3]]).channels[0];
+        assert_eq!((red.mean, red.clipped, red.crushed), (63.75, 25.0, 75.0));
+    }
+
+    #[test]
+    fn stats_of_nothing() {
+        assert!(ImageStats::of(&image(&[])).is_none());
+        assert!(ImageStats::of(&MyImage::default()).is_none());
+        // sized, but the pixels aren't there
+        let unloaded = MyImage {
+            width: 4,
+            height: 4,
+            ..Default::default()
+        };
+        let roi = Rect::from_min_max(pos2(0.25, 0.25), pos2(0.75, 0.75));
+        assert!(ImageStats::of(&region(&unloaded, Some(roi))).is_none());
+    }
+
+    /// 4x2 pixels, each one's red and green are its x and y.
+    fn grid() -> MyImage {
+        MyImage {
+            width: 4,
+            height: 2,
+            bytes: (0..2)
+                .flat_map(|y| (0..4).flat_map(move |x| [x, y, 0, 255]))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// The (x, y) of every pixel in the region from `min` to `max`.
+    fn metered(min: [f32; 2], max: [f32; 2]) -> Vec<[u8; 2]> {
+        let roi = Rect::from_min_max(min.into(), max.into());
+        let grid = grid();
+        let region = region(&grid, Some(roi));
+        assert_eq!(
+            region.bytes.len(),
+            MyImage::rgba_len(region.width, region.height)
+        );
+        region.bytes.chunks_exact(4).map(|p| [p[0], p[1]]).collect()
+    }
+
+    #[test]
+    fn region_covers_the_pixels_it_touches() {
+        assert!(matches!(region(&grid(), None), Cow::Borrowed(_)));
+        assert_eq!(
+            metered([0.0, 0.0], [1.0, 1.0]),
+            grid()
+                .bytes
+                .chunks_exact(4)
+                .map(|p| [p[0], p[1]])
+                .collect::<Vec<_>>()
+        );
+        // 1.2 to 2.4 pixels across, 0 to 1 down
+        assert_eq!(metered([0.3, 0.0], [0.6, 0.5]), [[1, 0], [2, 0]]);
+        assert_eq!(metered([0.9, 0.6], [1.0, 1.0]), [[3, 1]]);
+        // a sliver still gets the pixel it is in
+        assert_eq!(metered([0.5, 0.5], [0.51, 0.51]), [[2, 1]]);
+    }
+
+    #[test]
+    fn region_is_clamped_to_the_image() {
+        assert_eq!(metered([-0.5, -1.0], [0.25, 0.5]), [[0, 0]]);
+        assert_eq!(metered([0.75, 0.5], [1.5, 2.0]), [[3, 1]]);
+        assert_eq!(metered([-1.0, -1.0], [2.0, 2.0]).len(), 8);
+        // entirely off the image
+        assert!(metered([1.5, 0.0], [2.0, 1.0]).is_empty());
+        assert!(metered([0.0, -1.0], [1.0, -0.5]).is_empty());
+    }
+
+    #[test]
+    fn region_of_nothing_is_empty() {
+        let grid = grid();
+        for roi in [
+            Rect::from_min_max(pos2(0.5, 0.5), pos2(0.5, 0.5)),
+            Rect::from_min_max(pos2(0.25, 0.0), pos2(0.25, 1.0)),
+            Rect::from_min_max(pos2(0.0, 1.0), pos2(1.0, 1.0)),
+        ] {
+            let region = region(&grid, Some(roi));
+            assert_eq!((region.width, region.height), (0, 0));
+            assert!(ImageStats::of(&region).is_none());
+        }
+    }
+
+    #[test]
+    fn region_can_be_dragged_either_way() {
+        let expected = metered([0.3, 0.0], [0.6, 0.5]);
+        // the corners as they'd be dragging up and to the left, or just up
+        assert_eq!(metered([0.6, 0.5], [0.3, 0.0]), expected);
+        assert_eq!(metered([0.3, 0.5], [0.6, 0.0]), expected);
+        assert_eq!(metered([0.6, 0.0], [0.3, 0.5]), expected);
+    }
+
+    #[test]
+    fn analyses_are_redone_when_what_they_depend_on_changes() {
+        let photo = HistogramSource::Photo("a".to_owned());
+        let mut histogram = HistogramData {
+            source: photo.clone(),
+            ..Default::default()
+        };
+        assert!(histogram.key(&photo).is_none());
+        assert!(histogram.photo().is_none());
+        histogram
+            .analysed
+            .insert("a".to_owned(), (vec![], MyImage::default()));
+        assert!(histogram.photo().is_some());
+        assert!(histogram.key(&HistogramSource::Live).is_none());
+
+        let key = histogram.key(&photo);
+        assert!(key.is_some());
+        // how it is plotted doesn't matter
+        histogram.split = true;
+        histogram.compare_view = CompareView::SideBySide;
+        assert!(histogram.key(&photo) == key);
+
+        histogram.bins = 128;
+        assert!(histogram.key(&photo) != key);
+        let key = histogram.key(&photo);
+        histogram.roi = Some(Rect::from_min_max(pos2(0.0, 0.0), pos2(0.5, 0.5)));
+        assert!(histogram.key(&photo) != key);
+        let key = histogram.key(&photo);
+        histogram.analysed.get_mut("a").unwrap().0 = vec![FilterStep::default()];
+        assert!(histogram.key(&photo) != key);
+    }
 }
@@ -4,6 +4,14 @@ mod app;
 mod image;
 mod render;
 mod file_stuff;
+mod exif;
+mod file_name;
+mod filter;
+mod frame_source;
+mod gallery;
+mod gpu;
+mod history;
 mod image_info;
+mod photo_store;
 
 pub use app::MyApp;
@@ -0,0 +1,185 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use anyhow::anyhow;
+use js_sys::{Promise, Uint8Array};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{
+    window, IdbDatabase, IdbObjectStore, IdbOpenDbRequest, IdbRequest, IdbTransactionMode,
+};
+
+use super::PhotoStore;
+
+const DATABASE: &str = "webcamera";
+const STORE: &str = "photos";
+
+type Op = Box<dyn FnOnce(&IdbDatabase)>;
+
+#[derive(Default)]
+struct Shared {
+    db: Option<IdbDatabase>,
+    /// why the database couldn't be opened, nothing will work once this is set
+    error: Option<String>,
+    /// things asked for before the database finished opening
+    queued: Vec<Op>,
+    loading: HashSet<String>,
+    loaded: HashMap<String, Result<Vec<u8>, String>>,
+}
+
+/// Keeps photos in the browser's IndexedDB, which has far more room than local storage.
+pub struct IndexedDbStore(Rc<RefCell<Shared>>);
+
+fn js_error(e: JsValue) -> anyhow::Error {
+    anyhow!("{e:?}")
+}
+
+/// Waits for an IDB request to finish and returns its result.
+async fn wait(request: &IdbRequest) -> Result<JsValue, JsValue> {
+    let promise = Promise::new(&mut |resolve, reject| {
+        request.set_onsuccess(Some(&resolve));
+        request.set_onerror(Some(&reject));
+    });
+    JsFuture::from(promise).await?;
+    request.result()
+}
+
+fn object_store(db: &IdbDatabase, mode: IdbTransactionMode) -> Result<IdbObjectStore, JsValue> {
+    db.transaction_with_str_and_mode(STORE, mode)?
+        .object_store(STORE)
+}
+
+/// Runs a request in the background, only logging if it fails.
+fn spawn_request(what: String, request: Result<IdbRequest, JsValue>) {
+    spawn_local(async move {
+        let result = match request {
+            Ok(request) => wait(&request).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            log::error!("failed to {what}: {e:?}");
+        }
+    });
+}
+
+impl IndexedDbStore {
+    pub fn open() -> anyhow::Result<IndexedDbStore> {
+        let request = window()
+            .ok_or(anyhow!("no window"))?
+            .indexed_db()
+            .map_err(js_error)?
+            .ok_or(anyhow!("indexeddb is not available"))?
+            .open_with_u32(DATABASE, 1)
+            .map_err(js_error)?;
+
+        // runs the first time, before the database is handed over
+        let upgrade = Closure::once_into_js(|event: web_sys::Event| {
+            let Some(request) = event
+                .target()
+                .and_then(|t| t.dyn_into::<IdbOpenDbRequest>().ok())
+            else {
+                return;
+            };
+            if let Ok(db) = request.result().and_then(|db| db.dyn_into::<IdbDatabase>()) {
+                if !db.object_store_names().contains(STORE) {
+                    db.create_object_store(STORE).ok();
+                }
+            }
+        });
+        request.set_onupgradeneeded(Some(upgrade.unchecked_ref()));
+
+        let shared = Rc::new(RefCell::new(Shared::default()));
+        let opened = shared.clone();
+        spawn_local(async move {
+            match wait(&request)
+                .await
+                .and_then(|db| db.dyn_into::<IdbDatabase>())
+            {
+                Ok(db) => {
+                    opened.borrow_mut().db = Some(db.clone());
+                    let queued = std::mem::take(&mut opened.borrow_mut().queued);
+                    for op in queued {
+                        op(&db);
+                    }
+                }
+                Err(e) => {
+                    let error = format!("failed to open the photo database: {e:?}");
+                    log::error!("{error}");
+                    // whatever was waiting for the database never gets it
+                    let mut shared = opened.borrow_mut();
+                    shared.error = Some(error);
+                    shared.queued.clear();
+                    shared.loading.clear();
+                }
+            }
+        });
+        Ok(IndexedDbStore(shared))
+    }
+
+    fn run(&self, op: impl FnOnce(&IdbDatabase) + 'static) {
+        let db = self.0.borrow().db.clone();
+        match db {
+            Some(db) => op(&db),
+            None if self.0.borrow().error.is_some() => {}
+            None => self.0.borrow_mut().queued.push(Box::new(op)),
+        }
+    }
+}
+
+impl PhotoStore for IndexedDbStore {
+    fn put(&mut self, id: &str, data: Vec<u8>) {
+        let id = id.to_owned();
+        self.run(move |db| {
+            let request = object_store(db, IdbTransactionMode::Readwrite).and_then(|store| {
+                store.put_with_key(&Uint8Array::from(data.as_slice()), &id.as_str().into())
+            });
+            spawn_request(format!("store photo {id}"), request);
+        });
+    }
+
+    fn get(&mut self, id: &str) -> Option<anyhow::Result<Vec<u8>>> {
+        if let Some(result) = self.0.borrow_mut().loaded.remove(id) {
+            return Some(result.map_err(|e| anyhow!(e)));
+        }
+        if let Some(error) = &self.0.borrow().error {
+            return Some(Err(anyhow!("{error}")));
+        }
+        if !self.0.borrow_mut().loading.insert(id.to_owned()) {
+            return None;
+        }
+
+        let shared = self.0.clone();
+        let id = id.to_owned();
+        self.run(move |db| {
+            let request = object_store(db, IdbTransactionMode::Readonly)
+                .and_then(|store| store.get(&id.as_str().into()));
+            spawn_local(async move {
+                let result = match request {
+                    Ok(request) => wait(&request).await,
+                    Err(e) => Err(e),
+                };
+                let result = match result {
+                    Ok(value) if value.is_undefined() => Err(format!("photo {id} is missing")),
+                    Ok(value) => Ok(Uint8Array::new(&value).to_vec()),
+                    Err(e) => Err(format!("failed to load photo {id}: {e:?}")),
+                };
+                let mut shared = shared.borrow_mut();
+                shared.loading.remove(&id);
+                shared.loaded.insert(id, result);
+            });
+        });
+        None
+    }
+
+    fn remove(&mut self, id: &str) {
+        let id = id.to_owned();
+        self.run(move |db| {
+            let request = object_store(db, IdbTransactionMode::Readwrite)
+                .and_then(|store| store.delete(&id.as_str().into()));
+            spawn_request(format!("delete photo {id}"), request);
+        });
+    }
+}
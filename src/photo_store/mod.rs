@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+
+#[cfg(target_arch = "wasm32")]
+mod indexed_db;
+
+#[cfg(target_arch = "wasm32")]
+pub use indexed_db::IndexedDbStore;
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::DirectoryStore;
+
+/// Somewhere to keep the (compressed) pixels of every photo, so the app state only has to
+/// remember small bits of metadata. Photos are looked up by [`crate::image::MyImage::id`].
+///
+/// Stores may be asynchronous underneath, so `put` and `remove` are fire and forget and
+/// `get` is polled until the photo turns up.
+pub trait PhotoStore {
+    fn put(&mut self, id: &str, data: Vec<u8>);
+    /// `None` while the photo is still being fetched, ask again next frame.
+    fn get(&mut self, id: &str) -> Option<anyhow::Result<Vec<u8>>>;
+    fn remove(&mut self, id: &str);
+}
+
+/// Keeps everything in memory, for when there is nowhere better. Nothing survives a restart.
+#[derive(Default)]
+pub struct MemoryStore(HashMap<String, Vec<u8>>);
+
+impl PhotoStore for MemoryStore {
+    fn put(&mut self, id: &str, data: Vec<u8>) {
+        self.0.insert(id.to_owned(), data);
+    }
+
+    fn get(&mut self, id: &str) -> Option<anyhow::Result<Vec<u8>>> {
+        Some(
+            self.0
+                .get(id)
+                .cloned()
+                .ok_or(anyhow!("photo {id} is missing")),
+        )
+    }
+
+    fn remove(&mut self, id: &str) {
+        self.0.remove(id);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::path::PathBuf;
+
+    use super::PhotoStore;
+
+    /// One file per photo in a folder next to the rest of the app's saved state.
+    pub struct DirectoryStore(pub PathBuf);
+
+    impl DirectoryStore {
+        fn path(&self, id: &str) -> PathBuf {
+            self.0.join(format!("{id}.png"))
+        }
+    }
+
+    impl PhotoStore for DirectoryStore {
+        fn put(&mut self, id: &str, data: Vec<u8>) {
+            if let Err(e) =
+                std::fs::create_dir_all(&self.0).and_then(|_| std::fs::write(self.path(id), data))
+            {
+                log::error!("failed to store photo {id}: {e:?}");
+            }
+        }
+
+        fn get(&mut self, id: &str) -> Option<anyhow::Result<Vec<u8>>> {
+            Some(std::fs::read(self.path(id)).map_err(|e| e.into()))
+        }
+
+        fn remove(&mut self, id: &str) {
+            std::fs::remove_file(self.path(id)).ok();
+        }
+    }
+}
+
+/// The best store this platform has, falling back to memory.
+pub fn open() -> Box<dyn PhotoStore> {
+    #[cfg(not(target_arch = "wasm32"))]
+    let store = eframe::storage_dir("webcamera")
+        .map(|dir| DirectoryStore(dir.join("photos")))
+        .ok_or(anyhow!("no storage directory"));
+    #[cfg(target_arch = "wasm32")]
+    let store = IndexedDbStore::open();
+
+    match store {
+        Ok(store) => Box::new(store),
+        Err(e) => {
+            log::warn!("photos won't be kept after closing: {e:?}");
+            Box::<MemoryStore>::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(store: &mut dyn PhotoStore) {
+        assert!(store.get("a").unwrap().is_err());
+        store.put("a", vec![1, 2, 3]);
+        store.put("b", vec![4]);
+        assert_eq!(store.get("a").unwrap().unwrap(), [1, 2, 3]);
+        // putting again replaces the photo
+        store.put("a", vec![5, 6]);
+        assert_eq!(store.get("a").unwrap().unwrap(), [5, 6]);
+        store.remove("a");
+        assert!(store.get("a").unwrap().is_err());
+        assert_eq!(store.get("b").unwrap().unwrap(), [4]);
+        // removing something that isn't there is fine
+        store.remove("a");
+    }
+
+    #[test]
+    fn memory_store_round_trip() {
+        round_trip(&mut MemoryStore::default());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn directory_store_round_trip() {
+        let dir = std::env::temp_dir().join(format!("webcamera-store-{}", std::process::id()));
+        // the folder is only made once something is stored
+        round_trip(&mut DirectoryStore(dir.join("photos")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
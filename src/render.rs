@@ -1,161 +1,330 @@
-use anyhow::anyhow;
-use chrono::{DateTime, Local};
 use egui::{pos2, Color32, Rect, RichText, Ui};
 use egui_phosphor::regular::{CARET_LEFT, CARET_RIGHT};
+use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
-use web_sys::window;
 
-use crate::{app::SaveImageOptions, file_stuff::download_zip_file, MyApp};
+use crate::{
+    file_name::{export_names, TOKENS},
+    filter::render_filter_chain,
+    image::{format_size, selected_for_export, ExportFormat, Selection, Subsampling},
+    MyApp,
+};
 
-#[derive(serde::Deserialize, serde::Serialize, EnumIter, PartialEq)]
+#[derive(serde::Deserialize, serde::Serialize, EnumIter, PartialEq, Default)]
 pub enum UiTab {
+    #[default]
     TakePhoto,
+    Filters,
     Histogram,
     SavePhoto,
 }
 
-impl Default for UiTab {
-    fn default() -> Self {
-        UiTab::TakePhoto
-    }
-}
-
 impl UiTab {
     pub fn icon(&self) -> &str {
         match self {
             UiTab::TakePhoto => egui_phosphor::regular::APERTURE,
+            UiTab::Filters => egui_phosphor::regular::SLIDERS_HORIZONTAL,
             UiTab::SavePhoto => egui_phosphor::regular::FLOPPY_DISK,
             UiTab::Histogram => egui_phosphor::regular::PALETTE,
-            
         }
     }
 }
 
-
-
 impl MyApp {
     pub fn render_center(&mut self, ui: &mut Ui) -> anyhow::Result<()> {
         match self.ui_tab {
             UiTab::TakePhoto => {
                 self.render_photo_ui(ui)?;
-            },
+            }
+            UiTab::Filters => {
+                self.render_filters_ui(ui);
+            }
             UiTab::SavePhoto => {
                 self.render_save_ui(ui)?;
-            },
+            }
             UiTab::Histogram => {
                 self.render_histogram(ui);
-            },
+            }
         }
-        return Ok(())
+        return Ok(());
     }
 
-
     fn render_photo_ui(&mut self, ui: &mut Ui) -> anyhow::Result<()> {
         ui.vertical_centered(|ui| {
-            if ui.button(RichText::new("Take Photo").color(Color32::RED)).clicked() {
+            if ui
+                .button(RichText::new("Take Photo").color(Color32::RED))
+                .clicked()
+            {
                 self.take_photo(ui.ctx());
             }
         });
-        
-        match &self.texture {
-            Some(a) => {
-            }
-            None => {
-                ui.label("no image found");
-                if ui.small_button("reloading may help").clicked() {
-                    window().ok_or(anyhow!("failed to get window"))?.location().reload_with_forceget(true);
-                }
+        egui::CollapsingHeader::new(format!("source: {}", self.source.name()))
+            .id_salt("source")
+            .show(ui, |ui| {
+                self.render_source_ui(ui);
+            });
+
+        if self.texture.is_none() {
+            ui.label("no image found");
+            // reloading only makes sense in the browser
+            #[cfg(target_arch = "wasm32")]
+            if ui.small_button("reloading may help").clicked() {
+                web_sys::window()
+                    .ok_or(anyhow::anyhow!("failed to get window"))?
+                    .location()
+                    .reload_with_forceget(true)
+                    .map_err(|e| anyhow::anyhow!("{:?}", e))?;
             }
-            
         }
-        return Ok(());
+        Ok(())
     }
     fn render_save_ui(&mut self, ui: &mut Ui) -> anyhow::Result<(())> {
-        
         let index = self.save_options.image_index.max(0) as usize;
-        if index < self.photos.len() && !self.photos[index as usize].del {
+        self.render_undo_ui(ui);
+        if !self.photos.is_empty() {
+            egui::CollapsingHeader::new(format!("gallery ({})", self.photos.len()))
+                .id_salt("gallery")
+                .default_open(true)
+                .show(ui, |ui| self.render_gallery(ui));
+        }
+        let mut delete = false;
+        if index < self.photos.len() {
             ui.label(RichText::new(self.photos[index].name.clone()).strong());
             ui.horizontal(|ui| {
                 if ui.button(CARET_LEFT).clicked() {
                     self.save_options.image_index -= 1;
                     if self.save_options.image_index < 0 {
-                        self.save_options.image_index = self.photos.len() as i32 -1;
+                        self.save_options.image_index = self.photos.len() as i32 - 1;
                     }
 
-                    self.save_options.image_index = self.save_options.image_index % self.photos.len() as i32;
+                    self.save_options.image_index =
+                        self.save_options.image_index % self.photos.len() as i32;
                 };
                 ui.label(
-                    RichText::new(
-                    format!("{}/{}",(index+1).min(self.photos.len()),self.photos.len())).strong());
+                    RichText::new(format!(
+                        "{}/{}",
+                        (index + 1).min(self.photos.len()),
+                        self.photos.len()
+                    ))
+                    .strong(),
+                );
                 if ui.button(CARET_RIGHT).clicked() {
                     self.save_options.image_index += 1;
-                    self.save_options.image_index = self.save_options.image_index % self.photos.len() as i32;
+                    self.save_options.image_index =
+                        self.save_options.image_index % self.photos.len() as i32;
                 };
             });
             ui.checkbox(&mut self.photos[index].save, "save this image");
-            if ui.button("delete photo").clicked() {
-                self.photos[index as usize].del = true;
-            };
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(index > 0, egui::Button::new("move earlier").small())
+                    .clicked()
+                {
+                    self.move_photo(index, index - 1);
+                }
+                if ui
+                    .add_enabled(
+                        index + 1 < self.photos.len(),
+                        egui::Button::new("move later").small(),
+                    )
+                    .clicked()
+                {
+                    self.move_photo(index, index + 1);
+                }
+            });
+            let index = self.save_options.image_index as usize;
+            let before = self.photos[index].recipe.clone();
+            egui::CollapsingHeader::new(format!(
+                "edit ({} filters)",
+                self.photos[index].recipe.len()
+            ))
+            .id_salt("edit photo")
+            .show(ui, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    if ui.small_button("use current filters").clicked() {
+                        self.photos[index].recipe = self.filters.clone();
+                    }
+                    if ui.small_button("revert to original").clicked() {
+                        self.photos[index].recipe.clear();
+                    }
+                });
+                render_filter_chain(ui, &mut self.photos[index].recipe);
+            });
+            self.record_recipe(index, before);
+            ui.horizontal(|ui| {
+                let label = if cfg!(target_arch = "wasm32") {
+                    "download photo"
+                } else {
+                    "save photo"
+                };
+                if ui.button(label).clicked() {
+                    self.export_status = Some(match self.export_photo(index) {
+                        Ok(status) => status,
+                        Err(e) => format!("failed to save: {e}"),
+                    });
+                }
+                if ui
+                    .button("delete photo")
+                    .on_hover_text("moves it to the trash")
+                    .clicked()
+                {
+                    delete = true;
+                };
+            });
+
+            ui.separator();
+            let selected = selected_for_export(&self.photos).len();
+            ui.label(format!("{} photo/s selected to be saved", selected));
+            ui.horizontal_wrapped(|ui| {
+                for selection in Selection::iter() {
+                    if ui.small_button(selection.name()).clicked() {
+                        selection.apply(&mut self.photos);
+                    }
+                }
+            });
 
-                ui.separator();
-                ui.label(format!("{} photo/s selected to be saved", self.photos.iter().filter(|x|x.save).count()));
-                
-                // come back to this when i decide if I want to stor3 both raw and cooked photos
-                // ui.checkbox(&mut self.save_options.raw_png, "raw png");
+            egui::CollapsingHeader::new("file names").show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("template");
+                    ui.text_edit_singleline(&mut self.save_options.name_template)
+                        .on_hover_text(
+                            TOKENS
+                                .map(|(token, meaning)| format!("{token}  {meaning}"))
+                                .join("\n"),
+                        );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("prefix");
+                    ui.text_edit_singleline(&mut self.save_options.session_prefix)
+                        .on_hover_text("added to the start of every name until the app is closed");
+                });
+                let selected = selected_for_export(&self.photos);
+                let names = export_names(
+                    &self.save_options.name_template,
+                    &self.save_options.session_prefix,
+                    &self.photos,
+                    &selected,
+                );
+                for name in names.iter().take(3) {
+                    ui.label(RichText::new(name).italics().small());
+                }
+                if names.len() > 3 {
+                    ui.label(
+                        RichText::new(format!("and {} more", names.len() - 3))
+                            .italics()
+                            .small(),
+                    );
+                }
+            });
 
-                ui.checkbox(&mut self.save_options.jpg, "jpeg");
-                ui.checkbox(&mut self.save_options.png, "png");
+            ui.checkbox(&mut self.save_options.raw_png, "raw png (without filters)");
 
-                if ui.button("download").clicked() {
-                    let local: DateTime<Local> = Local::now();
-                    let photos = self.save_photos(ui.ctx()).unwrap();
-                    download_zip_file(photos, format!("{} Photos.zip", local.format("%Y-%m-%d %H-%M-%S").to_string())).unwrap();
+            ui.horizontal_wrapped(|ui| {
+                for format in ExportFormat::iter() {
+                    ui.checkbox(self.save_options.format_mut(format), format.name());
                 }
+            });
+            if self.save_options.jpg {
+                egui::CollapsingHeader::new("jpeg options").show(ui, |ui| {
+                    let jpeg = &mut self.save_options.jpeg;
+                    ui.add(egui::Slider::new(&mut jpeg.quality, 1..=100).text("quality"));
+                    egui::ComboBox::from_label("subsampling")
+                        .selected_text(jpeg.subsampling.name())
+                        .show_ui(ui, |ui| {
+                            for i in Subsampling::iter() {
+                                ui.selectable_value(&mut jpeg.subsampling, i, i.name());
+                            }
+                        });
+                    ui.checkbox(&mut jpeg.progressive, "progressive");
+                    ui.checkbox(&mut self.save_options.strip_metadata, "strip metadata")
+                        .on_hover_text("leave out the time, camera and filters");
+                    if let Some(size) = self.jpeg_size_estimate() {
+                        ui.label(format!("this photo will be about {}", format_size(size)));
+                    }
+                });
+            }
 
-        } else if self.photos.len() > 0 && self.photos[index as usize].del  && index < self.photos.len() {
-            ui.label(format!("are you sure you want to delete \"{}\"", self.photos[index].name));
-            if ui.button("no, cancel").clicked() {
-                self.photos[index].del = false;
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                ui.checkbox(&mut self.save_options.zip, "zip");
+                // only take the path once it has been typed out
+                let id = ui.make_persistent_id("export directory");
+                let mut directory = ui
+                    .data_mut(|d| d.get_temp::<String>(id))
+                    .unwrap_or_else(|| self.save_options.directory.clone());
+                ui.horizontal(|ui| {
+                    ui.label("folder");
+                    if ui.text_edit_singleline(&mut directory).lost_focus() {
+                        self.save_options.directory = directory.clone();
+                    }
+                });
+                ui.data_mut(|d| d.insert_temp(id, directory));
             }
-            if ui.button("yes, delete").clicked() {
-                self.photos.remove(index);
+
+            let label = if cfg!(target_arch = "wasm32") {
+                "download"
+            } else {
+                "save"
+            };
+            if ui
+                .add_enabled(selected > 0, egui::Button::new(label))
+                .clicked()
+            {
+                self.export_status = Some(match self.export_photos() {
+                    Ok(status) => status,
+                    Err(e) => format!("failed to save: {e}"),
+                });
+            }
+            if let Some(status) = &self.export_status {
+                ui.label(RichText::new(status).small());
             }
-        }else {
+        } else {
             ui.label("no photos have been taken");
         }
+        if delete {
+            self.trash_photo(self.save_options.image_index as usize);
+        }
+        // a slider has been let go of, the next edit gets its own undo
+        if !ui.input(|i| i.pointer.any_down()) {
+            self.history.close();
+        }
+        egui::CollapsingHeader::new(format!("trash ({})", self.trash.photos.len()))
+            .id_salt("trash")
+            .show(ui, |ui| self.render_trash_ui(ui));
         Ok(())
     }
 
     pub fn render_viewport(&mut self, ui: &mut Ui) {
+        let mut shown = None;
         match self.texture {
             // render image
             Some(ref a) => {
-                let mut image_rect = Rect::from_x_y_ranges(0.0..=a.size()[0] as f32, 0.0..=a.size()[1] as f32);
+                let mut image_rect =
+                    Rect::from_x_y_ranges(0.0..=a.size()[0] as f32, 0.0..=a.size()[1] as f32);
                 image_rect.set_center(ui.available_rect_before_wrap().center());
-                image_rect= image_rect.scale_from_center(
-                    (ui.available_rect_before_wrap().width()/a.size()[0] as f32)
-                    .min(
-                        ui.available_rect_before_wrap().height()/a.size()[1] as f32
-                    )
-                );
-                if self.photos.len() == 0 || self.ui_tab != UiTab::SavePhoto {
-                ui.label(
-                    RichText::new("low res preview").italics()
-                    .small()
-                    
+                image_rect = image_rect.scale_from_center(
+                    (ui.available_rect_before_wrap().width() / a.size()[0] as f32)
+                        .min(ui.available_rect_before_wrap().height() / a.size()[1] as f32),
                 );
+                if self.photos.is_empty() || self.ui_tab != UiTab::SavePhoto {
+                    ui.label(RichText::new("low res preview").italics().small());
                 }
-                ui.painter_at(image_rect).image(a.id(), 
-            
-                image_rect
-                , Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)), Color32::WHITE);
-            },
+                ui.painter_at(image_rect).image(
+                    a.id(),
+                    image_rect,
+                    Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)),
+                    Color32::WHITE,
+                );
+                shown = Some(image_rect);
+            }
             None => {
                 ui.label("failed to get video");
-            },
+            }
+        }
+        if let Some(image_rect) = shown {
+            if self.ui_tab == UiTab::Histogram {
+                self.render_roi(ui, image_rect);
+            }
         }
     }
-
 }
-
-